@@ -1,26 +1,69 @@
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use egui::{Align2, Color32, ColorImage, CursorIcon, Layout, Pos2, Sense, Stroke, TextureId, TextureOptions, Vec2, Vec2b};
+use egui::emath::Rot2;
+use egui::{Align2, Color32, ColorImage, CursorIcon, Key, KeyboardShortcut, Layout, Modifiers, Pos2, Sense, Stroke, TextureHandle, TextureId, TextureOptions, Vec2, Vec2b};
+use image::DynamicImage;
 use rfd::FileDialog;
 
+use crate::clipboard::{Clipboard, ClipboardError};
+use crate::export::{flatten_board, layer_image, save_board, EXPORT_EXTENSIONS};
+use crate::history::*;
+use crate::import::*;
+use crate::import_queue::*;
+use crate::layer_tree::*;
+use crate::perspective::*;
+use crate::project::*;
 use crate::resource::*;
+use crate::tiles::*;
+use crate::toast::Toasts;
+
+const HANDLE_SIZE: f32 = 8.;
+/// Screen distance of the rotation handle above the top edge of the layer.
+const ROTATE_HANDLE_DISTANCE: f32 = 24.;
+const CROP_HANDLE_SIZE: f32 = 15.;
+/// Smallest crop on screen, so the handles never cross each other.
+const MIN_CROP_SIZE: f32 = 8.;
 
 
 pub trait AppExt {
     fn new(cc: &eframe::CreationContext<'_>) -> Self;
-    fn import_image(&mut self,  ctx: &egui::Context);
+    fn import_images(&mut self, ctx: &egui::Context);
+    fn import_folder(&mut self, ctx: &egui::Context, recursive: bool);
+    fn add_imported_images(&mut self, ctx: &egui::Context, paths: Vec<PathBuf>, drop_pos: Option<Pos2>);
+    fn handle_file_drop(&mut self, ctx: &egui::Context);
+    fn receive_imports(&mut self, ctx: &egui::Context);
+    fn finish_restoring_project(&mut self);
+    fn draw_pending_imports(&mut self, ui: &mut egui::Ui);
+    fn display_pending_imports(&mut self, ui: &mut egui::Ui);
     fn select_image(&mut self, idx: usize);
+    fn activate_image(&mut self, idx: usize);
+    fn select_on_board(&mut self, idx: usize);
+    fn select_group(&mut self, group_id: u64);
+    fn group_selected_layers(&mut self);
+    fn ungroup_layers(&mut self, group_id: u64);
+    fn move_tree_item(&mut self, item: TreeItem, target: DropTarget);
+    fn display_layer_group_row(&mut self, ui: &mut egui::Ui, group_id: u64, cover_width: f32);
+    fn edit_layer_group(&mut self, group_id: u64, edit: impl FnOnce(&mut LayerGroup));
+    fn begin_group_edit(&mut self, ui: &egui::Ui, response: &egui::Response);
+    fn end_group_edit(&mut self, ui: &egui::Ui, response: &egui::Response);
+    fn record_arrangement(&mut self, before: LayerArrangement);
+    fn toggle_selection(&mut self, idx: usize);
+    fn select_range(&mut self, idx: usize);
+    fn selected_transforms(&self) -> Vec<(TextureId, ImageTranforms)>;
+    fn add_rubber_band_selection(&mut self, ctx: &egui::Context, ui: &mut egui::Ui);
+    fn draw_selection_outlines(&mut self, ui: &mut egui::Ui);
     fn add_image_to_state(&mut self);
     fn draw_image_to_board(
         &mut self, 
         ui: &mut egui::Ui, 
         image: &mut OutputTexture
-    ) -> egui::Rect;
+    ) -> [Pos2; 4];
     fn add_drag_events_to_image(
         &mut self, 
         ctx: &egui::Context,
         ui: &mut egui::Ui,
-        texture_rect: egui::Rect,
+        texture_quad: [Pos2; 4],
         idx: usize
     );
     fn add_main_image_rect_setting_control(
@@ -28,12 +71,44 @@ pub trait AppExt {
         ctx: &egui::Context,
         ui: &mut egui::Ui,
     );
+    fn handle_board_view_input(&mut self, ctx: &egui::Context, ui: &mut egui::Ui);
+    fn add_transform_handles(&mut self, ctx: &egui::Context, ui: &mut egui::Ui);
+    fn add_mask_handles(&mut self, ctx: &egui::Context, ui: &mut egui::Ui);
+    fn display_mask_settings(&mut self, ui: &mut egui::Ui);
     fn update_images_by_active_image(&mut self);
     fn update_croped_image_tranform(&mut self);
     fn display_image_for_cropping(&mut self, ui: &mut egui::Ui, image: &mut OutputTexture) -> egui::Rect;
     fn display_cropping_image_ui(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, raw_rect: egui::Rect);
+    fn display_perspective_crop_ui(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, raw_rect: egui::Rect);
+    fn display_crop_settings(&mut self, ui: &mut egui::Ui);
     fn remove_image(&mut self, image_id: TextureId);
+    fn record_layer_state(&mut self, idx: usize, before: LayerState);
+    fn evict_unused_tiles(&self, ctx: &egui::Context);
     fn display_sorting_images(&mut self, ctx: &egui::Context);
+    fn export_board(&mut self);
+    fn save_project(&mut self);
+    fn save_bundle(&mut self);
+    fn open_project(&mut self, ctx: &egui::Context);
+    fn load_project(&mut self, ctx: &egui::Context, path: PathBuf);
+    fn display_error_message(&mut self, ctx: &egui::Context);
+    fn undo(&mut self);
+    fn redo(&mut self);
+    fn handle_history_shortcuts(&mut self, ctx: &egui::Context);
+    fn record_history(&mut self, ctx: &egui::Context);
+    fn active_mask(&self) -> Option<(TextureId, LayerMask)>;
+    fn sync_active_image_after_history(&mut self);
+    fn apply_orientation(&mut self, op: OrientationOp);
+    fn bake_orientation(&mut self);
+    fn bake_crop(&mut self, ctx: &egui::Context);
+    fn apply_perspective_crop(&mut self, ctx: &egui::Context);
+    fn receive_perspective_crop(&mut self);
+    fn display_image_info(&mut self, ui: &mut egui::Ui);
+    fn display_board_settings(&mut self, ui: &mut egui::Ui);
+    fn push_layer(&mut self, layer: OutputTexture);
+    fn handle_clipboard_shortcuts(&mut self, ctx: &egui::Context);
+    fn paste_image(&mut self, ctx: &egui::Context);
+    fn copy_active_layer(&mut self);
+    fn copy_board(&mut self);
 }
 
 #[derive(Default)]
@@ -44,6 +119,34 @@ pub struct App {
     pub image_preview_pads: Vec<ImagePreviewPad>,
     pub active_image_settings: ActiveImageSettings,
     pub image_sorting_modify: ImageSortingModify,
+    pub error_message: Option<String>,
+    pub toasts: Toasts,
+    pub imports: ImportQueue,
+    pub clipboard: Clipboard,
+    pub history: History,
+    pub selection: BoardSelection,
+    pub layer_groups: Vec<LayerGroup>,
+    /// Set while a paste shortcut that already pasted on its press is held down.
+    pub is_paste_key_held: bool,
+    pub perspective_crop: Option<PendingPerspectiveCrop>,
+    pub restoring_project: Option<RestoringProject>,
+}
+
+/// A perspective crop being straightened in the background, applied to the layer once the pixels are ready.
+pub struct PendingPerspectiveCrop {
+    pub texture_id: TextureId,
+    pub before: LayerPixels,
+    /// The layer transform for the result, its size is only known when the thread is done.
+    pub transform: ImageTranforms,
+    pub job: RectifyJob,
+}
+
+/// A project being opened, its layers go through the import queue and are put on the board together once all are decoded.
+pub struct RestoringProject {
+    /// Saved layers in project order, bundled ones with their embedded bytes.
+    pub layers: Vec<(LayerManifest, Option<Arc<[u8]>>)>,
+    pub restored: Vec<Option<OutputTexture>>,
+    pub groups: Vec<LayerGroup>,
 }
 
 impl AppExt for App {
@@ -60,78 +163,558 @@ impl AppExt for App {
             active_image_settings: ActiveImageSettings::default(),
             image_preview_pads: Vec::new(),
             image_sorting_modify: ImageSortingModify::default(),
+            error_message: None,
+            toasts: Toasts::default(),
+            imports: ImportQueue::default(),
+            clipboard: Clipboard::default(),
+            history: History::default(),
+            selection: BoardSelection::default(),
+            layer_groups: Vec::new(),
+            is_paste_key_held: false,
+            perspective_crop: None,
+            restoring_project: None,
+        }
+    }
+    fn import_images(&mut self, ctx: &egui::Context) {
+        let mut dialog = FileDialog::new().add_filter("All supported images", &import_extensions());
+        for format in import_formats() {
+            dialog = dialog.add_filter(format!("{format:?}"), format.extensions_str());
+        }
+        let file_paths: Option<Vec<PathBuf>> = dialog.pick_files();
+        if let Some(paths) = file_paths {
+            self.add_imported_images(ctx, paths, None);
+        }
+    }
 
+    fn import_folder(&mut self, ctx: &egui::Context, recursive: bool) {
+        let Some(dir) = FileDialog::new().pick_folder() else {
+            return;
+        };
+        match collect_image_files(&dir, recursive) {
+            Ok(paths) if paths.is_empty() => {
+                self.toasts.warning(format!("No images found in {}", dir.display()));
+            }
+            Ok(paths) => self.add_imported_images(ctx, paths, None),
+            Err(err) => self.toasts.error(format!("Unable to read folder {}: {err}", dir.display())),
         }
     }
-    fn import_image(&mut self, ctx: &egui::Context) {
-        let file_path: Option<PathBuf> = FileDialog::new()
-            .add_filter("Image", &["png", "jpeg", "jpg"])
-            .pick_file();
-        match file_path {
-            Some(path) => {
-                let reader = image::ImageReader::open(path.clone()).unwrap();
-                if let Ok(image) = reader.decode()  {
-                    let image_size = Vec2::new(image.width() as f32, image.height() as f32);
-                    let image_ratio = calc_ratio(image_size.clone());
-                    let original_scale = calc_orignal_scale(Vec2::new(image.width() as f32, image.height() as f32), self.main_image_settings.image_plot_rect.size());
-                    let color_image = ColorImage::from_rgba_unmultiplied(
-                        [image.width() as _, image.height() as _],
-                        image.to_rgba8().as_flat_samples().as_slice(),
-                    );
-                    let texture_handled =  ctx.load_texture(
-                        "imported_image",
-                        color_image.clone(),
-                        TextureOptions::default(),
-                    );
-                    let loaded_texture = Some(OutputTexture {
-                        image: Some(image.clone()),
-                        file_path: Some(path),
-                        original_scale: original_scale.clone(),
-                        texture_handle: Some(texture_handled),
-                        image_ratio: image_ratio,
-                        transform: ImageTranforms { 
-                            size: image_size,
-                            original_scale: original_scale,
-                            ..Default::default()
-                        },
-                        ..Default::default()
-                    });
-                    // self.active_image_settings.transforms.pos = Pos2 { x: 0.0, y: 0.0 };
-                    // self.active_image_settings.transforms.scale = 1.;
-                    self.active_image_settings = ActiveImageSettings::default();
-                    self.active_image_settings.transforms = loaded_texture.clone().unwrap().transform.clone();
-                    self.active_image_settings.drag_offset = Vec2::new(0.0, 0.0);
-                    self.active_image =  Some(ActiveImage::new(loaded_texture.clone().unwrap().texture_handle.as_ref().unwrap().id() ));
-                    self.output_textures_vec.insert(0, loaded_texture.clone().unwrap());
-                    self.image_preview_pads.insert(0, ImagePreviewPad {
-                        
-                        texture: loaded_texture.clone().unwrap_or_default(),
-                    }); 
+
+    fn add_imported_images(&mut self, ctx: &egui::Context, paths: Vec<PathBuf>, drop_pos: Option<Pos2>) {
+        let board_settings = self.main_image_settings;
+        let max_texture_side = ctx.input(|i| i.max_texture_side) as u32;
+        // A single image goes to the drop position or the board center, several are spread over a grid around it
+        let visible_rect = board_settings.visible_board_rect();
+        let drop_pos = drop_pos.map(|drop_pos| board_settings.screen_to_board(drop_pos.clamp(visible_rect.min, visible_rect.max)));
+        let placements = match (paths.len(), drop_pos) {
+            (1, Some(drop_pos)) => vec![ImportPlacement::At(drop_pos)],
+            (1, None) => vec![ImportPlacement::Center],
+            (count, drop_pos) => {
+                let grid_offset = drop_pos.unwrap_or(Pos2::ZERO).to_vec2();
+                layout_cells(count, board_settings.board_size).into_iter().map(|cell| ImportPlacement::Cell(cell.translate(grid_offset))).collect()
+            }
+        };
+        for (path, placement) in paths.into_iter().zip(placements) {
+            self.imports.enqueue(ctx, path, placement, max_texture_side);
+        }
+    }
+
+    fn receive_imports(&mut self, ctx: &egui::Context) {
+        let board_size = self.main_image_settings.board_size;
+        for (pending, result) in self.imports.poll() {
+            // A file that fails is reported and skipped, the rest of the batch still gets imported
+            let DecodedLayer { image, orientation, metadata, color_image, levels } = match result {
+                Ok(decoded_layer) => decoded_layer,
+                Err(err) => {
+                    self.toasts.error(format!("Unable to import {}: {err}", pending.path.display()));
+                    continue;
+                }
+            };
+            let image_size = Vec2::new(image.width() as f32, image.height() as f32);
+            if let ImportPlacement::Restored(slot) = pending.placement
+                && let Some(project) = self.restoring_project.as_mut()
+                && let Some((layer, source_bytes)) = project.layers.get(slot).cloned() {
+                // Projects saved before layers had names fall back to the file stem
+                let name = if layer.name.is_empty() { OutputTexture::default_name(Some(&layer.file_path)) } else { layer.name };
+                project.restored[slot] = Some(OutputTexture {
+                    original_scale: layer.transform.original_scale,
+                    texture_handle: Some(load_texture_from_color_image(ctx, color_image)),
+                    tiles: levels.map(|levels| Arc::new(TiledImage::new(levels))),
+                    // Bundled layers only live inside the bundle
+                    file_path: if source_bytes.is_some() { None } else { Some(layer.file_path) },
+                    source_bytes,
+                    image_ratio: calc_ratio(image_size),
+                    transform: layer.transform,
+                    mask: layer.mask,
+                    name,
+                    is_visible: layer.is_visible,
+                    is_locked: layer.is_locked,
+                    group: layer.group,
+                    image: Some(image),
+                    metadata,
+                    ..Default::default()
+                });
+                continue;
+            }
+            let mut transform = ImageTranforms { size: image_size, ..Default::default() };
+            // Camera photos are shown upright through the same lossless flips and turns the user can apply
+            apply_exif_orientation(&mut transform, orientation);
+            transform.original_scale = calc_orignal_scale(transform.oriented_size(image_size), board_size);
+            place_imported_layer(&mut transform, pending.placement);
+            let loaded_texture = OutputTexture {
+                texture_handle: Some(load_texture_from_color_image(ctx, color_image)),
+                image: Some(image),
+                tiles: levels.map(|levels| Arc::new(TiledImage::new(levels))),
+                name: OutputTexture::default_name(Some(&pending.path)),
+                file_path: Some(pending.path),
+                original_scale: transform.original_scale,
+                image_ratio: calc_ratio(image_size),
+                transform,
+                metadata,
+                ..Default::default()
+            };
+            self.push_layer(loaded_texture);
+        }
+        self.finish_restoring_project();
+    }
+
+    /// Puts the layers of the opened project on the board once none of them is decoding anymore,
+    /// layers that failed or were cancelled are left out. Images imported meanwhile stay on top.
+    fn finish_restoring_project(&mut self) {
+        if self.restoring_project.is_none() || self.imports.pending().iter().any(|pending| matches!(pending.placement, ImportPlacement::Restored(_))) {
+            return;
+        }
+        let Some(project) = self.restoring_project.take() else {
+            return;
+        };
+        self.output_textures_vec.extend(project.restored.into_iter().flatten());
+        self.layer_groups = project.groups;
+        normalize(&mut self.output_textures_vec, &mut self.layer_groups);
+        self.image_preview_pads = self.output_textures_vec.iter().map(|texture| ImagePreviewPad { texture: texture.clone() }).collect();
+        self.history.clear();
+    }
+
+    fn draw_pending_imports(&mut self, ui: &mut egui::Ui) {
+        let board_settings = self.main_image_settings;
+        let painter = ui.painter().with_clip_rect(board_settings.visible_board_rect());
+        for pending in self.imports.pending() {
+            let placeholder_rect = match pending.placement {
+                ImportPlacement::Center => egui::Rect::from_center_size(board_settings.board_to_screen(Pos2::ZERO), Vec2::splat(160.)),
+                ImportPlacement::At(pos) => egui::Rect::from_center_size(board_settings.board_to_screen(pos), Vec2::splat(160.)),
+                ImportPlacement::Restored(slot) => {
+                    let pos = self.restoring_project.as_ref().and_then(|project| project.layers.get(slot)).map_or(Pos2::ZERO, |(layer, _)| layer.transform.pos);
+                    egui::Rect::from_center_size(board_settings.board_to_screen(pos), Vec2::splat(160.))
+                }
+                ImportPlacement::Cell(cell) => {
+                    let cell_rect = egui::Rect::from_min_max(board_settings.board_to_screen(cell.min), board_settings.board_to_screen(cell.max));
+                    cell_rect.shrink2(cell_rect.size() * 0.05)
+                }
+            };
+            painter.rect_filled(placeholder_rect, 4., Color32::from_black_alpha(60));
+            painter.rect_stroke(placeholder_rect, 4., Stroke::new(1., Color32::WHITE), egui::StrokeKind::Inside);
+            let spinner_rect = egui::Rect::from_center_size(placeholder_rect.center(), Vec2::splat(24.));
+            ui.put(spinner_rect, egui::Spinner::new().size(24.));
+            painter.text(
+                spinner_rect.center_bottom() + Vec2::new(0., 8.),
+                Align2::CENTER_TOP,
+                pending.path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default(),
+                egui::FontId::proportional(12.),
+                Color32::WHITE,
+            );
+        }
+    }
+
+    fn display_pending_imports(&mut self, ui: &mut egui::Ui) {
+        let mut cancelled = None;
+        for pending in self.imports.pending() {
+            ui.horizontal(|ui| {
+                ui.add(egui::Spinner::new());
+                let status = match pending.status {
+                    ImportStatus::Queued => "queued",
+                    ImportStatus::Decoding => "decoding",
                 };
-            },
-            _ => {
-                return;
+                let file_name = pending.path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                ui.label(format!("{file_name} ({status})"));
+                if ui.small_button(egui_phosphor::regular::X).on_hover_text("Cancel import").clicked() {
+                    cancelled = Some(pending.id);
+                }
+            });
+        }
+        if let Some(id) = cancelled {
+            self.imports.cancel(id);
+        }
+        if let Some(pending) = self.perspective_crop.as_ref() {
+            let name = self.output_textures_vec.iter().find(|image| image.texture_handle.as_ref().unwrap().id() == pending.texture_id).map(|image| image.name.clone()).unwrap_or_default();
+            ui.horizontal(|ui| {
+                ui.add(egui::Spinner::new());
+                ui.label(format!("{name} (straightening)"));
+            });
+        }
+    }
+
+    fn handle_file_drop(&mut self, ctx: &egui::Context) {
+        let (hovered_count, dropped_paths, pointer_pos) = ctx.input(|i| {
+            let dropped_paths: Vec<PathBuf> = i.raw.dropped_files.iter().filter_map(|file| file.path.clone()).collect();
+            (i.raw.hovered_files.len(), dropped_paths, i.pointer.latest_pos())
+        });
+        let is_cropping = self.active_image_settings.croped_modified.is_modifying;
+
+        if hovered_count > 0 {
+            let board_rect = self.main_image_settings.visible_board_rect();
+            let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("file_drop_target")));
+            let (fill, text) = if is_cropping {
+                (Color32::from_black_alpha(160), "Finish croping before importing".to_string())
+            } else {
+                (Color32::from_rgba_unmultiplied(40, 90, 160, 120), format!("Drop to import {hovered_count} item(s)"))
+            };
+            painter.rect_filled(board_rect, 4., fill);
+            painter.rect_stroke(board_rect.shrink(2.), 4., Stroke::new(3., Color32::WHITE), egui::StrokeKind::Inside);
+            painter.text(board_rect.center(), Align2::CENTER_CENTER, text, egui::FontId::proportional(24.), Color32::WHITE);
+        }
+
+        if dropped_paths.is_empty() {
+            return;
+        }
+        if is_cropping {
+            self.toasts.warning(format!("Finish croping before importing, {} dropped item(s) were not imported", dropped_paths.len()));
+            return;
+        }
+        // Dropped folders are walked with their subfolders
+        let mut paths = Vec::new();
+        for path in dropped_paths {
+            if !path.is_dir() {
+                paths.push(path);
+                continue;
             }
-        } 
-        
-        // self.load_color_image();
-        // self.load_texture(&egui::Context::default());
-        
-        // self.current_output_texture.as_ref().and_then(|ot| ot.image.clone())
+            match collect_image_files(&path, true) {
+                Ok(files) => paths.extend(files),
+                Err(err) => self.toasts.error(format!("Unable to read folder {}: {err}", path.display())),
+            }
+        }
+        if !paths.is_empty() {
+            self.add_imported_images(ctx, paths, pointer_pos);
+        }
     }
 
     fn select_image(&mut self, idx: usize) {
         // let picked_pad = self.image_preview_pads[idx].clone();
+        if self.active_image_settings.croped_modified.is_modifying {
+            return;
+        }
+        self.activate_image(idx);
+        self.selection.layers = vec![self.output_textures_vec[idx].texture_handle.as_ref().unwrap().id()];
+    }
+
+    /// Makes the layer the one the settings panel edits, the rest of the selection stays as it is.
+    fn activate_image(&mut self, idx: usize) {
         if self.active_image_settings.croped_modified.is_modifying {
             return;
         }
         let pick_texture: &OutputTexture = &self.output_textures_vec[idx];
-        self.active_image_settings.transforms = pick_texture.transform.clone();
-        self.active_image_settings.props = pick_texture.image_props.clone();
+        self.active_image_settings.transforms = pick_texture.transform;
+        self.active_image_settings.props = pick_texture.image_props;
         self.active_image_settings.croped_modified = CropedImageModified::default();
         self.active_image = Some(ActiveImage::new(pick_texture.texture_handle.as_ref().unwrap().id()));
         self.active_image_settings.drag_offset = Vec2::new(0.0, 0.0);
-        // Implement selection logic if needed
+    }
+
+    /// Layers in a group are picked together with the rest of their outermost group, they move as a unit.
+    fn select_on_board(&mut self, idx: usize) {
+        match outermost_group(&self.layer_groups, self.output_textures_vec[idx].group) {
+            Some(group_id) => {
+                self.select_group(group_id);
+                self.activate_image(idx);
+            }
+            None => self.select_image(idx),
+        }
+    }
+
+    fn select_group(&mut self, group_id: u64) {
+        if self.active_image_settings.croped_modified.is_modifying {
+            return;
+        }
+        let members = group_members(&self.output_textures_vec, &self.layer_groups, group_id);
+        let Some(first) = members.first().copied() else {
+            return;
+        };
+        self.selection.layers = members.iter().map(|idx| self.output_textures_vec[*idx].texture_handle.as_ref().unwrap().id()).collect();
+        self.activate_image(first);
+    }
+
+    fn group_selected_layers(&mut self) {
+        let before = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+        if group_layers(&mut self.output_textures_vec, &mut self.layer_groups, &self.selection.layers).is_some() {
+            let after = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+            self.history.push(BoardCommand::Arrange { before, after });
+        }
+    }
+
+    fn ungroup_layers(&mut self, group_id: u64) {
+        let before = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+        ungroup(&mut self.output_textures_vec, &mut self.layer_groups, group_id);
+        let after = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+        self.history.push(BoardCommand::Arrange { before, after });
+    }
+
+    fn move_tree_item(&mut self, item: TreeItem, target: DropTarget) {
+        let before = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+        if move_item(&mut self.output_textures_vec, &mut self.layer_groups, item, target) {
+            let after = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+            self.history.push(BoardCommand::Arrange { before, after });
+        }
+    }
+
+    fn display_layer_group_row(&mut self, ui: &mut egui::Ui, group_id: u64, cover_width: f32) {
+        let Some(group) = find_group(&self.layer_groups, group_id).cloned() else {
+            return;
+        };
+        let padding: f32 = 5.;
+        let (header_rect, header_res) = ui.allocate_exact_size(Vec2::new(cover_width, 30.), Sense::click_and_drag());
+        let members = group_members(&self.output_textures_vec, &self.layer_groups, group_id);
+        let is_selected = !members.is_empty() && members.iter().all(|idx| self.selection.contains(self.output_textures_vec[*idx].texture_handle.as_ref().unwrap().id()));
+        let header_color = if is_selected { Color32::from_rgb(200, 225, 255) } else { Color32::from_rgb(225, 225, 225) };
+        let text_color = if is_group_visible(&self.layer_groups, group_id) { Color32::BLACK } else { Color32::GRAY };
+        ui.painter().rect_filled(header_rect, 5., header_color);
+
+        if header_res.hovered() {
+            ui.ctx().set_cursor_icon(CursorIcon::PointingHand);
+        }
+        if header_res.clicked() {
+            self.select_group(group_id);
+        }
+        if header_res.drag_started() {
+            egui::DragAndDrop::set_payload(ui.ctx(), TreeItem::Group(group_id));
+        }
+        // The upper half drops above the group, the lower half into it
+        let drops_into = ui.ctx().pointer_hover_pos().is_some_and(|pos| pos.y > header_rect.center().y);
+        if header_res.dnd_hover_payload::<TreeItem>().is_some() {
+            paint_drop_hint(ui, header_rect, drops_into);
+        }
+        if let Some(item) = header_res.dnd_release_payload::<TreeItem>() {
+            let target = if drops_into { DropTarget::IntoGroup(group_id) } else { DropTarget::Above(TreeItem::Group(group_id)) };
+            self.move_tree_item(*item, target);
+        }
+
+        // Put on top of the header so they get the clicks, the rest of the header still selects the group
+        let caret_icon = if group.is_collapsed { egui_phosphor::regular::CARET_RIGHT } else { egui_phosphor::regular::CARET_DOWN };
+        let caret_rect = egui::Rect::from_center_size(Pos2::new(header_rect.min.x + padding + 8., header_rect.center().y), Vec2::splat(20.));
+        let caret = egui::Button::new(egui::RichText::new(caret_icon).size(14.0).color(Color32::BLACK)).frame(false);
+        if ui.put(caret_rect, caret).clicked() {
+            self.edit_layer_group(group_id, |group| group.is_collapsed = !group.is_collapsed);
+        }
+        ui.painter().text(
+            Pos2::new(caret_rect.max.x + padding, header_rect.center().y),
+            Align2::LEFT_CENTER,
+            egui_phosphor::regular::FOLDER,
+            egui::FontId::proportional(16.),
+            text_color,
+        );
+        let opacity_rect = egui::Rect::from_min_max(Pos2::new(header_rect.max.x - 50. - padding, header_rect.center().y - 10.), Pos2::new(header_rect.max.x - padding, header_rect.center().y + 10.));
+        let mut opacity = group.opacity;
+        let opacity_drag = egui::DragValue::new(&mut opacity).range(0.0..=1.0).speed(0.01).fixed_decimals(2);
+        let opacity_res = ui.put(opacity_rect, opacity_drag).on_hover_text("Group opacity");
+        self.begin_group_edit(ui, &opacity_res);
+        if opacity_res.changed()
+            && let Some(group) = self.layer_groups.iter_mut().find(|group| group.id == group_id) {
+            group.opacity = opacity;
+        }
+        self.end_group_edit(ui, &opacity_res);
+        let name_rect = egui::Rect::from_min_max(
+            Pos2::new(caret_rect.max.x + padding * 2. + 16., header_rect.center().y - 11.),
+            Pos2::new(opacity_rect.min.x - padding, header_rect.center().y + 11.),
+        );
+        if name_rect.is_positive() {
+            let mut name = group.name.clone();
+            let name_edit = egui::TextEdit::singleline(&mut name).hint_text("Group name").text_color(text_color).frame(false);
+            let name_res = ui.put(name_rect, name_edit);
+            self.begin_group_edit(ui, &name_res);
+            if name_res.changed()
+                && let Some(group) = self.layer_groups.iter_mut().find(|group| group.id == group_id) {
+                group.name = name;
+            }
+            self.end_group_edit(ui, &name_res);
+        }
+
+        let (visibility_icon, visibility_text) = if group.is_visible {
+            (egui_phosphor::regular::EYE, "Hide group")
+        } else {
+            (egui_phosphor::regular::EYE_SLASH, "Show group")
+        };
+        let (lock_icon, lock_text) = if group.is_locked {
+            (egui_phosphor::regular::LOCK, "Unlock group")
+        } else {
+            (egui_phosphor::regular::LOCK_OPEN, "Lock group")
+        };
+        ui.horizontal_centered(|ui| {
+            if ui.button(egui::RichText::new(visibility_icon).size(16.0)).on_hover_text(visibility_text).clicked() {
+                self.edit_layer_group(group_id, |group| group.is_visible = !group.is_visible);
+            }
+            if ui.button(egui::RichText::new(lock_icon).size(16.0)).on_hover_text(lock_text).clicked() {
+                self.edit_layer_group(group_id, |group| group.is_locked = !group.is_locked);
+            }
+            if ui.button(egui::RichText::new(egui_phosphor::regular::FOLDER_OPEN).size(16.0)).on_hover_text("Ungroup").clicked() {
+                self.ungroup_layers(group_id);
+            }
+        });
+    }
+
+    /// Changes a group from its row in the Images panel as one history entry.
+    fn edit_layer_group(&mut self, group_id: u64, edit: impl FnOnce(&mut LayerGroup)) {
+        let before = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+        if let Some(group) = self.layer_groups.iter_mut().find(|group| group.id == group_id) {
+            edit(group);
+        }
+        self.record_arrangement(before);
+    }
+
+    /// Keeps the arrangement from when a drag or text edit of a group row widget started,
+    /// call it before applying the change of this frame.
+    fn begin_group_edit(&mut self, ui: &egui::Ui, response: &egui::Response) {
+        if (response.drag_started() || response.gained_focus()) && ui.data(|data| data.get_temp::<LayerArrangement>(response.id).is_none()) {
+            let before = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+            ui.data_mut(|data| data.insert_temp(response.id, before));
+        }
+    }
+
+    /// Records the whole edit started in `begin_group_edit` as one entry once the drag or the text edit ends.
+    fn end_group_edit(&mut self, ui: &egui::Ui, response: &egui::Response) {
+        if !response.drag_stopped() && !response.lost_focus() {
+            return;
+        }
+        let before = ui.data_mut(|data| {
+            let before = data.get_temp::<LayerArrangement>(response.id);
+            data.remove::<LayerArrangement>(response.id);
+            before
+        });
+        if let Some(before) = before {
+            self.record_arrangement(before);
+        }
+    }
+
+    fn record_arrangement(&mut self, before: LayerArrangement) {
+        let after = LayerArrangement::capture(&self.output_textures_vec, &self.layer_groups);
+        if after != before {
+            self.history.push(BoardCommand::Arrange { before, after });
+        }
+    }
+
+    fn toggle_selection(&mut self, idx: usize) {
+        if self.active_image_settings.croped_modified.is_modifying {
+            return;
+        }
+        let texture_id = self.output_textures_vec[idx].texture_handle.as_ref().unwrap().id();
+        if !self.selection.contains(texture_id) {
+            self.selection.layers.push(texture_id);
+            self.activate_image(idx);
+            return;
+        }
+        self.selection.layers.retain(|id| *id != texture_id);
+        // The active layer hands over to the topmost layer still selected
+        if self.active_image.as_ref().is_some_and(|active_image| active_image.get_texture_id() == texture_id) {
+            match self.output_textures_vec.iter().position(|image| self.selection.contains(image.texture_handle.as_ref().unwrap().id())) {
+                Some(next_idx) => self.activate_image(next_idx),
+                None => self.active_image = None,
+            }
+        }
+    }
+
+    /// Adds every layer between the active one and `idx` in the panel order.
+    fn select_range(&mut self, idx: usize) {
+        if self.active_image_settings.croped_modified.is_modifying {
+            return;
+        }
+        let Some(active_idx) = self.active_image.as_ref().and_then(|active_image| {
+            self.output_textures_vec.iter().position(|image| image.texture_handle.as_ref().unwrap().id() == active_image.get_texture_id())
+        }) else {
+            self.select_image(idx);
+            return;
+        };
+        for range_idx in active_idx.min(idx)..=active_idx.max(idx) {
+            let texture_id = self.output_textures_vec[range_idx].texture_handle.as_ref().unwrap().id();
+            if !self.selection.contains(texture_id) {
+                self.selection.layers.push(texture_id);
+            }
+        }
+        self.activate_image(idx);
+    }
+
+    /// The active layer first, with the transform being edited, then the other selected layers.
+    fn selected_transforms(&self) -> Vec<(TextureId, ImageTranforms)> {
+        let Some(active_id) = self.active_image.as_ref().map(|active_image| active_image.get_texture_id()) else {
+            return Vec::new();
+        };
+        let others = self.output_textures_vec.iter()
+            .map(|image| (image.texture_handle.as_ref().unwrap().id(), image.transform))
+            .filter(|(texture_id, _)| *texture_id != active_id && self.selection.contains(*texture_id));
+        std::iter::once((active_id, self.active_image_settings.transforms)).chain(others).collect()
+    }
+
+    fn add_rubber_band_selection(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let board_settings = self.main_image_settings;
+        let is_panning = board_settings.view.is_panning || ctx.input(|i| i.key_down(Key::Space));
+        let sense = if is_panning { Sense::hover() } else { Sense::click_and_drag() };
+        let response = ui.interact(board_settings.viewport_rect, ui.id().with("rubber_band"), sense);
+        let modifiers = ctx.input(|i| i.modifiers);
+        let is_adding = modifiers.command || modifiers.shift;
+        if response.clicked() && !is_adding {
+            self.selection.layers.clear();
+            self.active_image = None;
+        }
+        if response.drag_started()
+            && let Some(pointer_pos) = response.interact_pointer_pos() {
+            let start = board_settings.screen_to_board(pointer_pos);
+            self.selection.rubber_band = Some((start, start));
+        }
+        if response.dragged()
+            && let Some(pointer_pos) = response.interact_pointer_pos()
+            && let Some((_, end)) = self.selection.rubber_band.as_mut() {
+            *end = board_settings.screen_to_board(pointer_pos);
+        }
+        if response.drag_stopped()
+            && let Some((start, end)) = self.selection.rubber_band.take() {
+            let band = egui::Rect::from_two_pos(start, end);
+            let board_rect = egui::Rect::from_center_size(Pos2::ZERO, board_settings.board_size);
+            let picked: Vec<TextureId> = self.output_textures_vec.iter()
+                .filter(|image| is_layer_visible(&self.layer_groups, image) && egui::Rect::from_points(&calc_layer_corners(image.image_size(), &image.transform, board_rect)).intersects(band))
+                .map(|image| image.texture_handle.as_ref().unwrap().id())
+                .collect();
+            if !is_adding {
+                self.selection.layers.clear();
+            }
+            for texture_id in picked {
+                if !self.selection.contains(texture_id) {
+                    self.selection.layers.push(texture_id);
+                }
+            }
+            // The active layer stays when it is still selected, otherwise the topmost picked layer takes over
+            let is_active_selected = self.active_image.as_ref().is_some_and(|active_image| self.selection.contains(active_image.get_texture_id()));
+            if !is_active_selected {
+                match self.output_textures_vec.iter().position(|image| self.selection.contains(image.texture_handle.as_ref().unwrap().id())) {
+                    Some(idx) => self.activate_image(idx),
+                    None => self.active_image = None,
+                }
+            }
+        }
+    }
+
+    fn draw_selection_outlines(&mut self, ui: &mut egui::Ui) {
+        let board_settings = self.main_image_settings;
+        let painter = ui.painter().with_clip_rect(board_settings.viewport_rect);
+        let selection_color = Color32::from_rgb(0, 150, 255);
+        // The active layer is outlined by its handles
+        let active_id = self.active_image.as_ref().map(|active_image| active_image.get_texture_id());
+        let board_rect = egui::Rect::from_center_size(Pos2::ZERO, board_settings.board_size);
+        for image in self.output_textures_vec.iter() {
+            let texture_id = image.texture_handle.as_ref().unwrap().id();
+            if !is_layer_visible(&self.layer_groups, image) || Some(texture_id) == active_id || !self.selection.contains(texture_id) {
+                continue;
+            }
+            let outline = calc_layer_corners(image.image_size(), &image.transform, board_rect).map(|corner| board_settings.board_to_screen(corner));
+            painter.add(egui::Shape::dashed_line(&[outline[0], outline[1], outline[2], outline[3], outline[0]], Stroke::new(1., selection_color), 6., 4.));
+        }
+        if let Some((start, end)) = self.selection.rubber_band {
+            let band = egui::Rect::from_two_pos(board_settings.board_to_screen(start), board_settings.board_to_screen(end));
+            painter.rect(band, 0., selection_color.gamma_multiply(0.15), Stroke::new(1., selection_color), egui::StrokeKind::Middle);
+        }
     }
 
     fn add_image_to_state(&mut self) {
@@ -141,47 +724,22 @@ impl AppExt for App {
         &mut self, 
         ui: &mut egui::Ui,
         image: &mut OutputTexture
-    ) -> egui::Rect {
-        let main_image_rect = self.main_image_settings.image_plot_rect;
+    ) -> [Pos2; 4] {
+        let board_rect = self.main_image_settings.image_plot_rect;
+        let main_image_rect = self.main_image_settings.visible_board_rect();
         let texture = image.texture_handle.as_ref().unwrap();
-        let croped = image.transform.croped.unwrap_or_default();
-        // let texture_props = &image.image_props;
-        // Calculate the scaled size while maintaining aspect ratio
-        let img_size = texture.size_vec2();
-        
-        // Scale the image based on the board size and scale factor
+        let image_size = image.image_size();
+        // Everything is placed in screen pixels, so the layer is seen through the board zoom
+        let transform = image.transform.zoomed(self.main_image_settings.view.zoom);
+        // Calculate the unclamped texture rectangle with panning and the croped part of it
+        let (unclamped_rect, croped_rect) = calc_layer_rects(image_size, &transform, board_rect);
+        let scaled_width = unclamped_rect.width();
+        let scaled_height = unclamped_rect.height();
 
-        // let scaled_height = main_image_rect.height() * image.scale;
-        // let scaled_width = scaled_height * aspect_ratio;
-        let scaled_height = img_size.y * image.original_scale * image.transform.scale;
-        let scaled_width = img_size.x * image.original_scale * image.transform.scale;
-        
-        // Center the image in the board
-        let center_x = main_image_rect.min.x + main_image_rect.width() / 2.0;
-        let center_y = main_image_rect.min.y + main_image_rect.height() / 2.0;
-        
-        // Apply pan offset
-        let offset = image.transform.pos;
-        
-        // Calculate the unclamped texture rectangle with panning
-        let unclamped_rect = egui::Rect::from_center_size(
-            Pos2::new(
-                center_x + offset.x,
-                center_y + offset.y
-            ),
-            Vec2::new(scaled_width, scaled_height)
-        );
-       
         // Clamp the texture rectangle to the board boundaries
         let texture_rect = egui::Rect {
-            min: Pos2::new(
-                (unclamped_rect.min.x + croped.left * image.transform.scale).max(main_image_rect.min.x).min(main_image_rect.max.x),
-                (unclamped_rect.min.y + croped.top * image.transform.scale).max(main_image_rect.min.y ).min(main_image_rect.max.y)
-            ),
-            max: Pos2::new(
-                 (unclamped_rect.max.x + croped.right * image.transform.scale).min(main_image_rect.max.x).max(main_image_rect.min.x),
-                 (unclamped_rect.max.y+ croped.bottom * image.transform.scale).min(main_image_rect.max.y).max(main_image_rect.min.y)
-            ),
+            min: croped_rect.min.clamp(main_image_rect.min, main_image_rect.max),
+            max: croped_rect.max.clamp(main_image_rect.min, main_image_rect.max),
         };
         // Calculate UV coordinates based on the clamped rectangle
         let uv_rect = egui::Rect::from_min_max(
@@ -196,48 +754,137 @@ impl AppExt for App {
         );
         
         image.image_props.uv_rect = uv_rect;
-        // Calculate rotation
-        ui.painter().image(
-            texture.id(), 
-            texture_rect, 
-            uv_rect, 
-            Color32::from_white_alpha((image.transform.opacity * 255.0) as u8)
-        );
+        let tint = Color32::from_white_alpha((image.transform.opacity * 255.0) as u8);
+        let texture_quad = calc_layer_corners(image_size, &transform, board_rect);
+        // A rotated layer can reach the board from outside of it, so its whole croped part is drawn and clipped
+        let (draw_rect, draw_uv_rect) = if image.transform.rotation == 0. {
+            (texture_rect, uv_rect)
+        } else {
+            let croped_uv_rect = egui::Rect::from_min_max(
+                ((croped_rect.min - unclamped_rect.min) / unclamped_rect.size()).to_pos2(),
+                ((croped_rect.max - unclamped_rect.min) / unclamped_rect.size()).to_pos2()
+            );
+            (croped_rect, croped_uv_rect)
+        };
+        let rotation = Rot2::from_angle(image.transform.rotation.to_radians());
+        let painter = ui.painter().with_clip_rect(main_image_rect);
+        let add_mesh = |mut mesh: egui::Mesh| {
+            if image.transform.rotation != 0. {
+                mesh.rotate(rotation, unclamped_rect.center());
+            }
+            painter.add(egui::Shape::mesh(mesh));
+        };
+
+        // Masked layers are cut into triangles of the mask shape, every texture piece takes its part of them
+        let mask_triangles = (image.mask != LayerMask::Rectangle).then(|| calc_mask_triangles(&image.mask, croped_rect));
+
+        // Large layers are drawn from the pyramid level matching the zoom, the preview fills in while tiles upload
+        let mut tile_meshes = Vec::new();
+        let mut is_complete = false;
+        if let Some(tiles) = image.tiles.as_ref()
+            && draw_rect.is_positive() {
+            let screen_pixels_per_source_pixel = scaled_width / image.transform.oriented_size(image_size).x;
+            let level = tiles.level_for_scale(screen_pixels_per_source_pixel);
+            if level + 1 < tiles.level_count() {
+                let visible_source_uv = oriented_to_source_uv_rect(draw_uv_rect, &image.transform);
+                let mut upload_budget = TILE_UPLOADS_PER_FRAME;
+                let (visible_tiles, complete) = tiles.visible_tiles(ui.ctx(), level, visible_source_uv, &mut upload_budget);
+                is_complete = complete;
+                for tile in visible_tiles {
+                    let piece_source_uv = tile.source_uv.intersect(visible_source_uv);
+                    if !piece_source_uv.is_positive() {
+                        continue;
+                    }
+                    let piece_uv = source_to_oriented_uv_rect(piece_source_uv, &image.transform);
+                    let piece_rect = egui::Rect::from_min_max(
+                        unclamped_rect.min + piece_uv.min.to_vec2() * unclamped_rect.size(),
+                        unclamped_rect.min + piece_uv.max.to_vec2() * unclamped_rect.size(),
+                    );
+                    if let Some(triangles) = mask_triangles.as_ref() {
+                        tile_meshes.push(masked_image_mesh(tile.texture_id, triangles, piece_rect, unclamped_rect, tile.source_uv, tint, &image.transform));
+                        continue;
+                    }
+                    let mut mesh = oriented_image_mesh(tile.texture_id, piece_rect, piece_uv, tint, &image.transform);
+                    // Source uv of the whole image into the uv of this tile
+                    for vertex in mesh.vertices.iter_mut() {
+                        vertex.uv = ((vertex.uv - tile.source_uv.min) / tile.source_uv.size()).to_pos2();
+                    }
+                    tile_meshes.push(mesh);
+                }
+                if !is_complete {
+                    ui.ctx().request_repaint();
+                }
+            }
+        }
+        if !is_complete {
+            if let Some(triangles) = mask_triangles.as_ref() {
+                let full_uv = egui::Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.));
+                add_mesh(masked_image_mesh(texture.id(), triangles, draw_rect, unclamped_rect, full_uv, tint, &image.transform));
+            } else {
+                add_mesh(oriented_image_mesh(texture.id(), draw_rect, draw_uv_rect, tint, &image.transform));
+            }
+        }
+        for mesh in tile_meshes {
+            add_mesh(mesh);
+        }
         
-        // Create triangles
-        texture_rect
+        texture_quad
     }
-    fn add_drag_events_to_image(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, texture_rect:egui::Rect, idx: usize ) {
+    fn add_drag_events_to_image(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, texture_quad: [Pos2; 4], idx: usize ) {
+        let main_image_rect = self.main_image_settings.visible_board_rect();
+        let bounding_rect = egui::Rect::from_points(&texture_quad);
+        let texture_rect = egui::Rect {
+            min: bounding_rect.min.clamp(main_image_rect.min, main_image_rect.max),
+            max: bounding_rect.max.clamp(main_image_rect.min, main_image_rect.max),
+        };
+        // Only sense clicks inside the (rotated) shape so the layers below stay reachable through its corners
+        let mask = &self.output_textures_vec[idx].mask;
+        let is_pointer_inside = ctx.pointer_latest_pos().is_some_and(|pointer_pos| {
+            main_image_rect.contains(pointer_pos) && is_point_in_quad(pointer_pos, &texture_quad) && is_point_in_mask(pointer_pos, &texture_quad, mask)
+        });
+        let is_dragging_this = self.active_image_settings.is_dragging && self.active_image.as_ref().is_some_and(|active_image| {
+            active_image.get_texture_id() == self.output_textures_vec[idx].texture_handle.as_ref().unwrap().id()
+        });
+        let is_panning = self.main_image_settings.view.is_panning || ctx.input(|i| i.key_down(Key::Space));
+        // A locked layer can still be picked, it only ignores drags
+        let is_locked = is_layer_locked(&self.layer_groups, &self.output_textures_vec[idx]);
+        let sense = match (!is_panning && (is_pointer_inside || is_dragging_this), is_locked) {
+            (true, false) => Sense::click_and_drag(),
+            (true, true) => Sense::click(),
+            (false, _) => Sense::hover(),
+        };
 
-        let click_drag_respone = ui.allocate_rect(texture_rect, Sense::click_and_drag());
-        if click_drag_respone.hovered() {
+        let click_drag_respone = ui.allocate_rect(texture_rect, sense);
+        if click_drag_respone.hovered() && is_pointer_inside && !is_locked {
             ctx.set_cursor_icon(CursorIcon::Grab);
         }
         if click_drag_respone.clicked() {
-          
-            self.select_image(idx);
-        
+            let modifiers = ctx.input(|i| i.modifiers);
+            if modifiers.command || modifiers.shift {
+                self.toggle_selection(idx);
+            } else {
+                self.select_on_board(idx);
+            }
         }
         if click_drag_respone.drag_started() {
-            self.select_image(idx);
+            // Dragging a selected layer moves the whole selection
+            if self.selection.contains(self.output_textures_vec[idx].texture_handle.as_ref().unwrap().id()) {
+                self.activate_image(idx);
+            } else {
+                self.select_on_board(idx);
+            }
 
             self.active_image_settings.is_dragging = true;
             
-            self.active_image_settings.drag_offset = click_drag_respone.interact_pointer_pos().unwrap() as Pos2 - self.active_image_settings.transforms.pos as Pos2;
+            // Layer positions are in board units, the pointer is brought through the view first
+            let pointer_pos = self.main_image_settings.screen_to_board(click_drag_respone.interact_pointer_pos().unwrap());
+            self.active_image_settings.drag_offset = pointer_pos - self.active_image_settings.transforms.pos as Pos2;
         }
         if self.active_image_settings.is_dragging && click_drag_respone.dragged() {
             ctx.set_cursor_icon(CursorIcon::Grabbing);
             if let Some(pointer_pos) = click_drag_respone.interact_pointer_pos() {
-                self.active_image_settings.transforms.pos = pointer_pos - self.active_image_settings.drag_offset;
-                // let texture = self.find_texture(idx)
-                if self.active_image.is_some() {
-                    for output_texture in self.output_textures_vec.iter_mut() {
-                        if self.active_image.as_ref().unwrap().get_texture_id() == output_texture.texture_handle.as_ref().unwrap().id() {
-                            output_texture.transform.pos = self.active_image_settings.transforms.pos.clone();
-                            
-                        }
-                    }   
-                }
+                self.active_image_settings.transforms.pos = self.main_image_settings.screen_to_board(pointer_pos) - self.active_image_settings.drag_offset;
+                self.update_images_by_active_image();
             }
         }
         if click_drag_respone.drag_stopped() {
@@ -291,6 +938,8 @@ impl AppExt for App {
             if let Some(pointer_pos) = right_side_drag_response.interact_pointer_pos() {
                 self.main_image_settings.right_side_drag_pos = pointer_pos - Vec2::new(self.main_image_settings.right_side_drag_offset, 0.0);
                 self.main_image_settings.image_plot_rect.max.x = self.main_image_settings.right_side_drag_pos.x;
+                let board_size = Vec2::new(self.main_image_settings.image_plot_rect.width() / self.main_image_settings.view.zoom, self.main_image_settings.board_size.y);
+                self.main_image_settings.set_board_size(board_size);
                 // let texture = self.find_texture(idx)
                 
             }
@@ -312,6 +961,8 @@ impl AppExt for App {
             if let Some(pointer_pos) = bottom_side_drag_response.interact_pointer_pos() {
                 self.main_image_settings.bottom_side_drag_pos = pointer_pos - Vec2::new(0.0, self.main_image_settings.bottom_side_drag_offset);
                 self.main_image_settings.image_plot_rect.max.y = self.main_image_settings.bottom_side_drag_pos.y;
+                let board_size = Vec2::new(self.main_image_settings.board_size.x, self.main_image_settings.image_plot_rect.height() / self.main_image_settings.view.zoom);
+                self.main_image_settings.set_board_size(board_size);
                 // let texture = self.find_texture(idx)
                 
             }
@@ -321,213 +972,456 @@ impl AppExt for App {
             self.main_image_settings.is_dragging = false;
         }
     }
-    fn update_images_by_active_image(&mut self) {
+    fn handle_board_view_input(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        let viewport_rect = self.main_image_settings.viewport_rect;
+        // Popups and windows above the board keep their own scrolling
+        let hover_pos = ctx.input(|i| i.pointer.hover_pos())
+            .filter(|pos| viewport_rect.contains(*pos) && ctx.layer_id_at(*pos).is_none_or(|layer| layer == ui.layer_id()));
+        let (scroll, zoom_delta, space_down, middle_pressed, primary_pressed, middle_down, primary_down, pointer_delta) = ctx.input(|i| (
+            i.smooth_scroll_delta.y,
+            i.zoom_delta(),
+            i.key_down(Key::Space),
+            i.pointer.button_pressed(egui::PointerButton::Middle),
+            i.pointer.primary_pressed(),
+            i.pointer.middle_down(),
+            i.pointer.primary_down(),
+            i.pointer.delta(),
+        ));
 
-       
-       let find_image = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == self.active_image.as_ref().unwrap().get_texture_id());
-       if let Some(image) = find_image {
-            image.transform.scale = self.active_image_settings.transforms.scale.clone();
-            image.transform.opacity = self.active_image_settings.transforms.opacity.clone();
-            image.transform.rotation = self.active_image_settings.transforms.rotation.clone();
-            image.transform.pos = self.active_image_settings.transforms.pos.clone();
-            self.active_image_settings.props.to_owned().uv_rect = image.image_props.uv_rect.clone();
-       }
+        let view = &mut self.main_image_settings.view;
+        if let Some(pos) = hover_pos {
+            let factor = zoom_delta * (scroll * 0.002).exp();
+            if factor != 1. {
+                view.zoom_at(viewport_rect, pos, factor);
+            }
+            if middle_pressed || (space_down && primary_pressed) {
+                view.is_panning = true;
+            }
+            if space_down && !view.is_panning {
+                ctx.set_cursor_icon(CursorIcon::Grab);
+            }
+        }
+        if view.is_panning {
+            if middle_down || (space_down && primary_down) {
+                view.pan += pointer_delta;
+                ctx.set_cursor_icon(CursorIcon::Grabbing);
+            } else {
+                view.is_panning = false;
+            }
+        }
+        self.main_image_settings.update_board_rect(viewport_rect);
     }
-    fn display_cropping_image_ui(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, raw_rect: egui::Rect) {
-        let mut current_modified = self.active_image_settings.croped_modified.current_modified.unwrap_or_default().clone();
-        let main_rect = self.main_image_settings.image_plot_rect;
-        let crop_line_stroke = Stroke::new(2., Color32::from_rgb(255, 255, 255));
-
-        // raw rect
-        let mut clone_raw_rect = raw_rect.clone();
-        let unclamped_raw_rect = raw_rect.clone();
-        clone_raw_rect.min = clone_raw_rect.min.clamp(self.main_image_settings.image_plot_rect.min, self.main_image_settings.image_plot_rect.max);
-        clone_raw_rect.max = clone_raw_rect.max.clamp(self.main_image_settings.image_plot_rect.min, self.main_image_settings.image_plot_rect.max);
-        // ui.painter().rect_stroke(
-        //     clone_raw_rect, 
-        //     0.0,
-        //     Stroke::new(1.0, Color32::from_rgb(255,25, 25)), 
-            
-        //     StrokeKind::Middle
-        // );
+    fn add_transform_handles(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        if self.active_image_settings.croped_modified.is_modifying {
+            return;
+        }
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        // Hidden and locked layers get no handles
+        let Some(image_size) = self.output_textures_vec.iter()
+            .find(|image| image.texture_handle.as_ref().unwrap().id() == active_image.get_texture_id())
+            .filter(|image| is_layer_visible(&self.layer_groups, image) && !is_layer_locked(&self.layer_groups, image))
+            .map(|image| image.image_size()) else {
+            return;
+        };
+        let board_settings = self.main_image_settings;
+        let transforms = self.active_image_settings.transforms;
+        let rotation = Rot2::from_angle(transforms.rotation.to_radians());
+        let local_rect = calc_layer_local_rect(image_size, &transforms);
+        let to_screen = |local: Pos2| board_settings.board_to_screen(transforms.pos + rotation * local.to_vec2());
 
-        
-        let modified_top = ((unclamped_raw_rect.min.y  + current_modified.top  ).max(main_rect.min.y)).min(main_rect.max.y);
-        let modified_left = ((unclamped_raw_rect.min.x  + current_modified.left  ).max(main_rect.min.x)).min(main_rect.max.x);
-        let modified_bottom = ((unclamped_raw_rect.max.y  + current_modified.bottom   ).min(main_rect.max.y)).max(main_rect.min.y);
-        let modified_right = ((unclamped_raw_rect.max.x  + current_modified.right  ).min(main_rect.max.x)).max(main_rect.min.x);
-        
-        
-            
-        if !(raw_rect.min.x < main_rect.min.x || raw_rect.min.y < main_rect.min.y) {
-            let top_left_rect = egui::Rect {
-                min: Pos2::new(modified_left , modified_top ),
-                max: Pos2::new(modified_left + 15.0, modified_top + 15.0).min(Pos2::new(modified_right, modified_bottom ))
-            };
-            ui.painter().rect_filled(top_left_rect, 0.0, Color32::from_rgb(255, 255, 255));
-            let top_drag_sense = ui.allocate_rect(
-                top_left_rect.clone(), Sense::click_and_drag()
-            );
+        let painter = ui.painter().with_clip_rect(board_settings.viewport_rect);
+        let handle_color = Color32::from_rgb(0, 150, 255);
+        let outline = [local_rect.left_top(), local_rect.right_top(), local_rect.right_bottom(), local_rect.left_bottom()].map(to_screen);
+        painter.add(egui::Shape::closed_line(outline.to_vec(), Stroke::new(1., handle_color)));
 
+        let is_panning = board_settings.view.is_panning || ctx.input(|i| i.key_down(Key::Space));
+        let top_center = to_screen(local_rect.center_top());
+        let rotate_pos = top_center + rotation * Vec2::new(0., -ROTATE_HANDLE_DISTANCE);
+        painter.line_segment([top_center, rotate_pos], Stroke::new(1., handle_color));
 
-            if top_drag_sense.hovered() {
-                ctx.set_cursor_icon(CursorIcon::Grabbing);
+        for handle in TRANSFORM_HANDLES {
+            let handle_pos = match handle {
+                TransformHandle::Scale { x, y } => to_screen(handle_local_pos(local_rect, x, y)),
+                TransformHandle::Rotate => rotate_pos,
             };
+            let handle_rect = egui::Rect::from_center_size(handle_pos, Vec2::splat(HANDLE_SIZE * 1.5));
+            let sense = if is_panning { Sense::hover() } else { Sense::drag() };
+            let response = ui.interact(handle_rect, ui.id().with(("transform_handle", handle)), sense);
+            let fill = if response.hovered() || response.dragged() { handle_color } else { Color32::WHITE };
+            match handle {
+                TransformHandle::Scale { .. } => {
+                    painter.rect(egui::Rect::from_center_size(handle_pos, Vec2::splat(HANDLE_SIZE)), 0., fill, Stroke::new(1., handle_color), egui::StrokeKind::Middle);
+                }
+                TransformHandle::Rotate => {
+                    painter.circle(handle_pos, HANDLE_SIZE / 2., fill, Stroke::new(1., handle_color));
+                }
+            }
+            if is_panning {
+                continue;
+            }
+            let cursor = match handle {
+                TransformHandle::Scale { .. } => resize_cursor(handle_pos - to_screen(local_rect.center())),
+                TransformHandle::Rotate => CursorIcon::Crosshair,
+            };
+            if response.hovered() || response.dragged() {
+                ctx.set_cursor_icon(cursor);
+            }
 
-            if top_drag_sense.drag_started() {
-                self.active_image_settings.croped_modified.crop_drag_state.is_dragging = true;
-                let starting_pos =  unclamped_raw_rect.min + Vec2::new(current_modified.left, current_modified.top);
-                // let starting_pos =  unclamped_raw_rect.min + Vec2::new(self.active_image_settings.croped_modified.current_modified.unwrap_or_default().left, self.active_image_settings.croped_modified.current_modified.unwrap_or_default().top);
-                let interaction_pos = top_drag_sense.interact_pointer_pos().unwrap() as Pos2;
-                // let starting_pos =  unclamped_raw_rect.min.y + self.active_image_settings.croped_modified.current_modified.unwrap_or_default().top;
-                self.active_image_settings.croped_modified.crop_drag_state.drag_pos = starting_pos;
-                self.active_image_settings.croped_modified.crop_drag_state.drag_offset = interaction_pos - self.active_image_settings.croped_modified.crop_drag_state.drag_pos;
+            if response.drag_started()
+                && let Some(pointer_pos) = response.interact_pointer_pos() {
+                self.active_image_settings.transform_drag = Some(TransformDrag {
+                    handle,
+                    start: transforms,
+                    start_pointer: board_settings.screen_to_board(pointer_pos),
+                });
             }
-            if self.active_image_settings.croped_modified.crop_drag_state.is_dragging && top_drag_sense.dragged() {
-                ctx.set_cursor_icon(CursorIcon::Grabbing);
-                if let Some(pointer_pos) = top_drag_sense.interact_pointer_pos() {
-                    let clamp_pos = pointer_pos.clamp(
-                        unclamped_raw_rect.min ,
-                        unclamped_raw_rect.max + Vec2::new(current_modified.right, current_modified.bottom)
-                    );
-                    let crop = (clamp_pos - raw_rect.min.to_vec2()) - (self.active_image_settings.croped_modified.crop_drag_state.drag_offset ); 
-                    // pos = pos ;
-                    // let top =   ;
-                    // pos = pos.clamp(raw_rect.min, raw_rect.max);
-                    current_modified.top = crop.y.min(raw_rect.height() - current_modified.bottom).max(0.);
-                    current_modified.left = crop.x.min(raw_rect.width() - current_modified.right).max(0.);
-                    self.active_image_settings.croped_modified.current_modified = Some(current_modified.clone());
-                 
+            if response.dragged()
+                && let Some(drag) = self.active_image_settings.transform_drag.filter(|drag| drag.handle == handle)
+                && let Some(pointer_pos) = response.interact_pointer_pos() {
+                let pointer = board_settings.screen_to_board(pointer_pos);
+                let modifiers = ctx.input(|i| i.modifiers);
+                let updated = match handle {
+                    TransformHandle::Scale { x, y } => scale_with_handle(image_size, &drag.start, x, y, pointer, modifiers.shift, modifiers.alt),
+                    TransformHandle::Rotate => rotate_with_handle(&drag.start, drag.start_pointer, pointer, modifiers.shift),
+                };
+                let active_transforms = &mut self.active_image_settings.transforms;
+                active_transforms.scale = updated.scale;
+                active_transforms.aspect_stretch = updated.aspect_stretch;
+                active_transforms.pos = updated.pos;
+                active_transforms.rotation = updated.rotation;
+            }
+            if response.drag_stopped() {
+                self.active_image_settings.transform_drag = None;
+            }
+        }
+    }
+    fn add_mask_handles(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        if self.active_image_settings.croped_modified.is_modifying {
+            return;
+        }
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        let Some(layer) = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == active_image.get_texture_id()) else {
+            return;
+        };
+        if !is_layer_visible(&self.layer_groups, layer) || is_layer_locked(&self.layer_groups, layer) {
+            return;
+        }
+        let image_size = layer.image_size();
+        let LayerMask::Polygon(points) = &mut layer.mask else {
+            return;
+        };
+        let board_settings = self.main_image_settings;
+        let transforms = self.active_image_settings.transforms;
+        let rotation = Rot2::from_angle(transforms.rotation.to_radians());
+        let local_rect = calc_layer_local_rect(image_size, &transforms);
+        let to_screen = |uv: Pos2| board_settings.board_to_screen(transforms.pos + rotation * (local_rect.min + uv.to_vec2() * local_rect.size()).to_vec2());
+
+        let painter = ui.painter().with_clip_rect(board_settings.viewport_rect);
+        let point_color = Color32::from_rgb(255, 160, 0);
+        painter.add(egui::Shape::closed_line(points.iter().map(|point| to_screen(*point)).collect(), Stroke::new(1., point_color)));
+        let is_panning = board_settings.view.is_panning || ctx.input(|i| i.key_down(Key::Space));
+        for (idx, point) in points.iter_mut().enumerate() {
+            let point_pos = to_screen(*point);
+            let sense = if is_panning { Sense::hover() } else { Sense::drag() };
+            let response = ui.interact(egui::Rect::from_center_size(point_pos, Vec2::splat(HANDLE_SIZE * 1.5)), ui.id().with(("mask_point", idx)), sense);
+            let fill = if response.hovered() || response.dragged() { point_color } else { Color32::WHITE };
+            painter.circle(point_pos, HANDLE_SIZE / 2., fill, Stroke::new(1., point_color));
+            if response.hovered() || response.dragged() {
+                ctx.set_cursor_icon(CursorIcon::Move);
+            }
+            if response.dragged()
+                && let Some(pointer_pos) = response.interact_pointer_pos() {
+                let local = rotation.inverse() * (board_settings.screen_to_board(pointer_pos) - transforms.pos);
+                let uv = ((local - local_rect.min.to_vec2()) / local_rect.size()).to_pos2();
+                *point = uv.clamp(Pos2::ZERO, Pos2::new(1., 1.));
+            }
+        }
+    }
+
+    fn display_mask_settings(&mut self, ui: &mut egui::Ui) {
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        let Some(layer) = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == active_image.get_texture_id()) else {
+            return;
+        };
+        let mask = &mut layer.mask;
+        egui::ComboBox::from_label("Mask").selected_text(mask.label()).show_ui(ui, |ui| {
+            for option in [LayerMask::Rectangle, LayerMask::Ellipse, LayerMask::RoundedRect(0.15), LayerMask::default_polygon()] {
+                let is_selected = std::mem::discriminant(&*mask) == std::mem::discriminant(&option);
+                if ui.selectable_label(is_selected, option.label()).clicked() && !is_selected {
+                    *mask = option;
                 }
             }
-            if top_drag_sense.drag_stopped() {
-                ctx.set_cursor_icon(CursorIcon::Alias);
-                self.active_image_settings.croped_modified.crop_drag_state.is_dragging = false;
+        });
+        match mask {
+            LayerMask::RoundedRect(radius) => {
+                ui.add(egui::Slider::new(radius, 0.0..=0.5).text("Corner radius"));
+            }
+            LayerMask::Polygon(points) => {
+                ui.horizontal(|ui| {
+                    if ui.button("Add Point").on_hover_text("Split the longest side of the outline").clicked() {
+                        let longest = (0..points.len())
+                            .max_by(|a, b| {
+                                let length = |idx: usize| (points[(idx + 1) % points.len()] - points[idx]).length_sq();
+                                length(*a).total_cmp(&length(*b))
+                            })
+                            .unwrap_or(0);
+                        let middle = points[longest].lerp(points[(longest + 1) % points.len()], 0.5);
+                        points.insert(longest + 1, middle);
+                    }
+                    if ui.add_enabled(points.len() > 3, egui::Button::new("Remove Point")).clicked() {
+                        points.pop();
+                    }
+                });
+                ui.label("Drag the points on the board to shape the mask");
             }
+            LayerMask::Rectangle | LayerMask::Ellipse => {}
+        }
+    }
 
+    fn update_images_by_active_image(&mut self) {
 
+       
+       let active_id = self.active_image.as_ref().unwrap().get_texture_id();
+       let find_image = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == active_id);
+       if let Some(image) = find_image {
+            let previous = image.transform;
+            image.transform.scale = self.active_image_settings.transforms.scale;
+            image.transform.aspect_stretch = self.active_image_settings.transforms.aspect_stretch;
+            image.transform.opacity = self.active_image_settings.transforms.opacity;
+            image.transform.rotation = self.active_image_settings.transforms.rotation;
+            image.transform.pos = self.active_image_settings.transforms.pos;
+            self.active_image_settings.props.to_owned().uv_rect = image.image_props.uv_rect;
+
+            // The rest of the selection follows, scaled about the same fixed point as the active layer
+            let current = self.active_image_settings.transforms;
+            if self.selection.is_group() && previous != current {
+                let opacity_delta = current.opacity - previous.opacity;
+                let mut followers: Vec<&mut ImageTranforms> = self.output_textures_vec
+                    .iter_mut()
+                    .filter(|image| {
+                        let texture_id = image.texture_handle.as_ref().unwrap().id();
+                        texture_id != active_id
+                            && self.selection.contains(texture_id)
+                            && is_layer_visible(&self.layer_groups, image)
+                            && !is_layer_locked(&self.layer_groups, image)
+                    })
+                    .map(|image| &mut image.transform)
+                    .collect();
+                let active = follow_active_layer(&previous, &current, &mut followers);
+                for transform in followers {
+                    transform.opacity = (transform.opacity + opacity_delta).clamp(0., 1.);
+                }
+                if active != current {
+                    self.active_image_settings.transforms = active;
+                    if let Some(image) = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == active_id) {
+                        image.transform = active;
+                    }
+                }
+            }
+       }
+    }
+    fn display_cropping_image_ui(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, raw_rect: egui::Rect) {
+        if self.active_image_settings.croped_modified.mode == CropMode::Perspective {
+            self.display_perspective_crop_ui(ctx, ui, raw_rect);
+            return;
         }
+        // The crop is kept in board units and handled in screen pixels here
+        let zoom = self.main_image_settings.view.zoom;
+        let current_modified = self.active_image_settings.croped_modified.current_modified.unwrap_or_default().scaled(zoom);
+        let main_rect = self.main_image_settings.visible_board_rect();
+        let crop_line_stroke = Stroke::new(2., Color32::from_rgb(255, 255, 255));
+        let painter = ui.painter().with_clip_rect(main_rect);
+        // The displayed image keeps the pixel aspect, so the ratio holds on screen as well
+        let aspect = self.active_image_settings.croped_modified.aspect.ratio(raw_rect.size());
 
-        if !(raw_rect.max.x > main_rect.max.x || raw_rect.max.y > main_rect.max.y) {
-            let bottom_right_rect = egui::Rect {
-                min: Pos2::new(modified_right - 15.0, modified_bottom - 15.0 ).max(Pos2::new(modified_left , modified_top)),
-                max: Pos2::new(modified_right, modified_bottom )
-            };
-            ui.painter().rect_filled(bottom_right_rect.clone(), 0.0, Color32::from_rgb(255, 255, 255));
-            let bottom_drag_sense = ui.allocate_rect(
-                bottom_right_rect.clone(), Sense::click_and_drag()
-            );
+        let crop_rect = egui::Rect {
+            min: raw_rect.min + Vec2::new(current_modified.left, current_modified.top),
+            max: raw_rect.max + Vec2::new(current_modified.right, current_modified.bottom),
+        };
+        // raw rect
+        painter.rect_stroke(raw_rect, 0.0, Stroke::new(1.0, Color32::from_rgb(255, 25, 25)), egui::StrokeKind::Middle);
+        painter.rect_stroke(crop_rect, 0.0, crop_line_stroke, egui::StrokeKind::Middle);
+        // Rule of thirds
+        let thirds_stroke = Stroke::new(1., Color32::from_white_alpha(140));
+        for third in [1. / 3., 2. / 3.] {
+            let x = crop_rect.min.x + crop_rect.width() * third;
+            let y = crop_rect.min.y + crop_rect.height() * third;
+            painter.line_segment([Pos2::new(x, crop_rect.min.y), Pos2::new(x, crop_rect.max.y)], thirds_stroke);
+            painter.line_segment([Pos2::new(crop_rect.min.x, y), Pos2::new(crop_rect.max.x, y)], thirds_stroke);
+        }
 
+        // The body comes first so the handles on top of it win the drag.
+        // Handles outside of the visible board are pulled to its border so they can always be grabbed
+        let handle_area = main_rect.shrink(CROP_HANDLE_SIZE / 2.);
+        let mut crop_targets = vec![(None, crop_rect.intersect(main_rect), CursorIcon::Move)];
+        for handle in TRANSFORM_HANDLES {
+            if let TransformHandle::Scale { x, y } = handle {
+                let handle_pos = handle_local_pos(crop_rect, x, y).clamp(handle_area.min, handle_area.max);
+                let handle_rect = egui::Rect::from_center_size(handle_pos, Vec2::splat(CROP_HANDLE_SIZE));
+                crop_targets.push((Some((x, y)), handle_rect, resize_cursor(Vec2::new(x as f32, y as f32))));
+            }
+        }
 
-            if bottom_drag_sense.hovered() {
-                ctx.set_cursor_icon(CursorIcon::Grabbing);
-            };
+        for (handle, target_rect, cursor) in crop_targets {
+            if !target_rect.is_positive() {
+                continue;
+            }
+            let response = ui.interact(target_rect, ui.id().with(("crop_handle", handle)), Sense::drag());
+            if handle.is_some() {
+                let fill = if response.hovered() || response.dragged() { Color32::from_rgb(0, 150, 255) } else { Color32::WHITE };
+                ui.painter().rect_filled(target_rect.shrink(2.), 0.0, fill);
+            }
+            if response.hovered() || response.dragged() {
+                ctx.set_cursor_icon(cursor);
+            }
 
-            if bottom_drag_sense.drag_started() {
-                self.active_image_settings.croped_modified.crop_drag_state.is_dragging = true;
-                let starting_pos =  unclamped_raw_rect.max + Vec2::new(current_modified.right, current_modified.bottom);
-                // let starting_pos =  unclamped_raw_rect.min + Vec2::new(self.active_image_settings.croped_modified.current_modified.unwrap_or_default().left, self.active_image_settings.croped_modified.current_modified.unwrap_or_default().top);
-                let interaction_pos = bottom_drag_sense.interact_pointer_pos().unwrap() as Pos2;
-                // let starting_pos =  unclamped_raw_rect.min.y + self.active_image_settings.croped_modified.current_modified.unwrap_or_default().top;
-                self.active_image_settings.croped_modified.crop_drag_state.drag_pos = starting_pos;
-                self.active_image_settings.croped_modified.crop_drag_state.drag_offset = interaction_pos - self.active_image_settings.croped_modified.crop_drag_state.drag_pos;
+            let drag_state = &mut self.active_image_settings.croped_modified.crop_drag_state;
+            if response.drag_started()
+                && let Some(pointer_pos) = response.interact_pointer_pos() {
+                drag_state.is_dragging = true;
+                drag_state.drag_pos = pointer_pos;
+                drag_state.start_crop = current_modified;
             }
-            if self.active_image_settings.croped_modified.crop_drag_state.is_dragging && bottom_drag_sense.dragged() {
-                ctx.set_cursor_icon(CursorIcon::Grabbing);
-                if let Some(pointer_pos) = bottom_drag_sense.interact_pointer_pos() {
-                    let clamp_pos = pointer_pos.clamp(
-                        unclamped_raw_rect.min + Vec2::new(current_modified.left, current_modified.top),
-                        unclamped_raw_rect.max
-                    );
-                    let crop = (clamp_pos - raw_rect.max.to_vec2()) - (self.active_image_settings.croped_modified.crop_drag_state.drag_offset ); 
-                    // pos = pos ;
-                    // let top =   ;
-                    // pos = pos.clamp(raw_rect.min, raw_rect.max);
-                    current_modified.bottom = crop.y.max(-(raw_rect.height() - current_modified.top)).min(0.);
-                    current_modified.right = crop.x.max(-(raw_rect.width() - current_modified.left)).min(0.);
-                    self.active_image_settings.croped_modified.current_modified = Some(current_modified.clone());
-                 
-                }
+            if drag_state.is_dragging && response.dragged()
+                && let Some(pointer_pos) = response.interact_pointer_pos() {
+                let croped = crop_with_handle(drag_state.start_crop, raw_rect.size(), handle, pointer_pos - drag_state.drag_pos, aspect);
+                self.active_image_settings.croped_modified.current_modified = Some(croped.scaled(1. / zoom));
             }
-            if bottom_drag_sense.drag_stopped() {
-                ctx.set_cursor_icon(CursorIcon::Alias);
+            if response.drag_stopped() {
                 self.active_image_settings.croped_modified.crop_drag_state.is_dragging = false;
             }
         }
-        // }
-        if !(raw_rect.min.y  < main_rect.min.y)  { 
-            ui.painter().line(Vec::from([
-                Pos2::new(clone_raw_rect.min.x, clone_raw_rect.min.y ),
-                Pos2::new(clone_raw_rect.max.x, clone_raw_rect.min.y )
-            ]), Stroke::new(1.0, Color32::from_rgb(255,25, 25)));
+    }
+    fn display_perspective_crop_ui(&mut self, ctx: &egui::Context, ui: &mut egui::Ui, raw_rect: egui::Rect) {
+        let main_rect = self.main_image_settings.visible_board_rect();
+        let painter = ui.painter().with_clip_rect(main_rect);
+        let corners = &mut self.active_image_settings.croped_modified.perspective;
+        let to_screen = |uv: Pos2| raw_rect.min + uv.to_vec2() * raw_rect.size();
+        let screen_quad = corners.map(to_screen);
+        let line_color = if is_convex_quad(*corners) { Color32::WHITE } else { Color32::from_rgb(255, 25, 25) };
 
+        painter.rect_stroke(raw_rect, 0.0, Stroke::new(1.0, Color32::from_rgb(255, 25, 25)), egui::StrokeKind::Middle);
+        painter.add(egui::Shape::closed_line(screen_quad.to_vec(), Stroke::new(2., line_color)));
+        // The thirds follow the perspective, so they line up with the document once it sits inside the quad
+        let homography = Homography::square_to_quad(screen_quad);
+        let thirds_stroke = Stroke::new(1., Color32::from_white_alpha(140));
+        for third in [1. / 3., 2. / 3.] {
+            painter.line_segment([homography.map(Pos2::new(third, 0.)), homography.map(Pos2::new(third, 1.))], thirds_stroke);
+            painter.line_segment([homography.map(Pos2::new(0., third)), homography.map(Pos2::new(1., third))], thirds_stroke);
+        }
+
+        let handle_area = main_rect.shrink(CROP_HANDLE_SIZE / 2.);
+        for (idx, corner) in corners.iter_mut().enumerate() {
+            let handle_pos = to_screen(*corner).clamp(handle_area.min, handle_area.max);
+            let response = ui.interact(egui::Rect::from_center_size(handle_pos, Vec2::splat(CROP_HANDLE_SIZE)), ui.id().with(("perspective_corner", idx)), Sense::drag());
+            let fill = if response.hovered() || response.dragged() { Color32::from_rgb(0, 150, 255) } else { Color32::WHITE };
+            painter.circle(handle_pos, CROP_HANDLE_SIZE / 2. - 2., fill, Stroke::new(1., Color32::BLACK));
+            if response.hovered() || response.dragged() {
+                ctx.set_cursor_icon(CursorIcon::Crosshair);
+            }
+            if response.dragged()
+                && let Some(pointer_pos) = response.interact_pointer_pos() {
+                *corner = ((pointer_pos.clamp(raw_rect.min, raw_rect.max) - raw_rect.min) / raw_rect.size()).to_pos2();
+            }
         }
-            // raw line
-            
-        let _top_line = ui.painter().line(Vec::from([
-            Pos2::new(modified_left,  modified_top),
-            Pos2::new(modified_right, modified_top)
-        ]), crop_line_stroke);
-        
-            
-        
-        
-        if !(raw_rect.min.x < main_rect.min.x) {
-            // raw line
-            ui.painter().line(Vec::from([
-                Pos2::new(clone_raw_rect.min.x, clone_raw_rect.min.y ),
-                Pos2::new(clone_raw_rect.min.x, clone_raw_rect.max.y )
-            ]), Stroke::new(1.0, Color32::from_rgb(255,25, 25)));
-        }
-            // let left_line = ui.painter().line(Vec::from([
-            //     Pos2::new(clone_raw_rect.min.x + current_modified.left, clone_raw_rect.min.y + current_modified.top),
-            //     Pos2::new(clone_raw_rect.min.x + current_modified.left, clone_raw_rect.max.y + current_modified.bottom)
-            // ]), crop_line_stroke);
-            let _left_line = ui.painter().line(Vec::from([
-                Pos2::new(modified_left, modified_top),
-                Pos2::new(modified_left, modified_bottom)
-            ]), crop_line_stroke);
-        // }
-        
-        if !(raw_rect.max.y > main_rect.max.y) {
-            // raw line
-            ui.painter().line(Vec::from([
-                Pos2::new(clone_raw_rect.min.x, clone_raw_rect.max.y ),
-                Pos2::new(clone_raw_rect.max.x, clone_raw_rect.max.y )
-            ]), Stroke::new(1.0, Color32::from_rgb(255,25, 25)));
-        }
-            // let bottom_line = ui.painter().line(Vec::from([
-            //     Pos2::new(clone_raw_rect.min.x + current_modified.left, clone_raw_rect.max.y + current_modified.bottom ),
-            //     Pos2::new(clone_raw_rect.max.x + current_modified.right, clone_raw_rect.max.y + current_modified.bottom)
-            // ]), crop_line_stroke);
-            let _bottom_line = ui.painter().line(Vec::from([
-                Pos2::new(modified_left, modified_bottom ),
-                Pos2::new(modified_right, modified_bottom)
-            ]), crop_line_stroke);
-        // }
-    
-        if !(raw_rect.max.x > main_rect.max.x) {
-             // raw line
-            ui.painter().line(Vec::from([
-                Pos2::new(clone_raw_rect.max.x, clone_raw_rect.min.y ),
-                Pos2::new(clone_raw_rect.max.x, clone_raw_rect.max.y )
-            ]), Stroke::new(1.0, Color32::from_rgb(255,25, 25)));
-        }
-            // let right_line = ui.painter().line(Vec::from([
-            //     Pos2::new(clone_raw_rect.max.x + current_modified.right, clone_raw_rect.min.y + current_modified.top),
-            //     Pos2::new(clone_raw_rect.max.x + current_modified.right, clone_raw_rect.max.y + current_modified.bottom)
-            // ]), crop_line_stroke);
-        let _right_line = ui.painter().line(Vec::from([
-            Pos2::new(modified_right, modified_top),
-            Pos2::new(modified_right, modified_bottom)
-        ]), crop_line_stroke);
-        // let texture_rect = self.active_image_settings;
-        // ui.painter().line(points, stroke)
+    }
+
+    fn display_crop_settings(&mut self, ui: &mut egui::Ui) {
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        let Some(image_size) = self.output_textures_vec.iter()
+            .find(|image| image.texture_handle.as_ref().unwrap().id() == active_image.get_texture_id())
+            .map(|image| image.image_size()) else {
+            return;
+        };
+        // Everything here is in pixels of the oriented image, whatever the crop view scale is
+        let transforms = self.active_image_settings.transforms;
+        let image_size = transforms.oriented_size(image_size);
+        let bounds = egui::Rect::from_min_size(Pos2::ZERO, image_size);
+        let croped_modified = &mut self.active_image_settings.croped_modified;
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut croped_modified.mode, CropMode::Rectangle, "Rectangle");
+            ui.selectable_value(&mut croped_modified.mode, CropMode::Perspective, "Perspective")
+                .on_hover_text("Drag the four corners onto the edges of a photographed document to straighten it");
+        });
+        if croped_modified.mode == CropMode::Perspective {
+            let (width, height) = rectified_size(croped_modified.perspective.map(|uv| (uv.to_vec2() * image_size).to_pos2()));
+            if is_convex_quad(croped_modified.perspective) {
+                ui.label(format!("Result: {width} × {height} px"));
+            } else {
+                ui.colored_label(Color32::LIGHT_RED, "The corners cross, move them back into a convex shape");
+            }
+            return;
+        }
+        let unit = croped_modified.max_scale * transforms.original_scale;
+        let mut pixel_rect = crop_to_pixel_rect(croped_modified.current_modified.unwrap_or_default(), image_size, unit);
+
+        let mut aspect = croped_modified.aspect;
+        egui::ComboBox::from_label("Aspect").selected_text(aspect.label()).show_ui(ui, |ui| {
+            for preset in CROP_ASPECTS {
+                let is_selected = match (aspect, preset) {
+                    (CropAspect::Custom(..), CropAspect::Custom(..)) => true,
+                    _ => aspect == preset,
+                };
+                if ui.selectable_label(is_selected, preset.label()).clicked() && !is_selected {
+                    aspect = preset;
+                }
+            }
+        });
+        if let CropAspect::Custom(width, height) = &mut aspect {
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(width).range(0.1..=100.).speed(0.1));
+                ui.label(":");
+                ui.add(egui::DragValue::new(height).range(0.1..=100.).speed(0.1));
+            });
+        }
+        let ratio = aspect.ratio(image_size);
+        let mut is_changed = aspect != croped_modified.aspect;
+        croped_modified.aspect = aspect;
+        if is_changed && let Some(ratio) = ratio {
+            pixel_rect = fit_crop_aspect(pixel_rect, bounds, ratio);
+        }
+
+        let (mut x, mut y) = (pixel_rect.min.x.round(), pixel_rect.min.y.round());
+        let (mut width, mut height) = (pixel_rect.width().round(), pixel_rect.height().round());
+        ui.horizontal(|ui| {
+            is_changed |= ui.add(egui::DragValue::new(&mut x).range(0.0..=image_size.x - 1.).speed(1.).prefix("X: ").suffix(" px")).changed();
+            is_changed |= ui.add(egui::DragValue::new(&mut y).range(0.0..=image_size.y - 1.).speed(1.).prefix("Y: ").suffix(" px")).changed();
+        });
+        ui.horizontal(|ui| {
+            let is_width_changed = ui.add(egui::DragValue::new(&mut width).range(1.0..=image_size.x).speed(1.).prefix("W: ").suffix(" px")).changed();
+            let is_height_changed = ui.add(egui::DragValue::new(&mut height).range(1.0..=image_size.y).speed(1.).prefix("H: ").suffix(" px")).changed();
+            if let Some(ratio) = ratio {
+                if is_width_changed {
+                    height = (width / ratio).round();
+                } else if is_height_changed {
+                    width = (height * ratio).round();
+                }
+            }
+            is_changed |= is_width_changed || is_height_changed;
+        });
+        if is_changed {
+            let mut size = Vec2::new(width, height);
+            if size.x > image_size.x || size.y > image_size.y {
+                size *= (image_size.x / size.x).min(image_size.y / size.y);
+            }
+            let min = Pos2::new(x, y).clamp(Pos2::ZERO, (image_size - size).to_pos2());
+            pixel_rect = egui::Rect::from_min_size(min, size);
+            croped_modified.current_modified = Some(crop_from_pixel_rect(pixel_rect, image_size, unit));
+        }
+        ui.label(format!("Result: {} × {} px", pixel_rect.width().round() as u32, pixel_rect.height().round() as u32));
     }
     fn update_croped_image_tranform(&mut self) {
-        let current_crop = self.active_image_settings.croped_modified.current_modified.clone();
+        let current_crop = self.active_image_settings.croped_modified.current_modified;
         // let scaled = self.active_image_settings.transforms.scale.clone();
-        let scaled = self.active_image_settings.croped_modified.max_scale.clone();
+        let scaled = self.active_image_settings.croped_modified.max_scale;
         let multiply = 1. / scaled;
         if let Some(croped) = current_crop {
             let dest = CropRect{top: croped.top * multiply, left: croped.left * multiply , bottom: croped.bottom * multiply, right: croped.right * multiply };
@@ -535,24 +1429,26 @@ impl AppExt for App {
         } else {
             self.active_image_settings.croped_modified.dest_modified = None;
         };
-        self.active_image_settings.transforms.croped = self.active_image_settings.croped_modified.dest_modified.clone();
+        self.active_image_settings.transforms.croped = self.active_image_settings.croped_modified.dest_modified;
         for output_texture in self.output_textures_vec.iter_mut() {
             if self.active_image.as_ref().unwrap().get_texture_id() == output_texture.texture_handle.as_ref().unwrap().id() {
-                output_texture.transform.croped = self.active_image_settings.transforms.croped.clone();
+                output_texture.transform.croped = self.active_image_settings.transforms.croped;
             }
         }    
     }
     fn display_image_for_cropping(&mut self, ui: &mut egui::Ui, image: &mut OutputTexture) -> egui::Rect {
-        let main_image_rect = self.main_image_settings.image_plot_rect;
-        let max_scale = self.active_image_settings.croped_modified.max_scale.clone() * image.transform.original_scale;
+        let main_image_rect = self.main_image_settings.visible_board_rect();
+        let board_center = self.main_image_settings.image_plot_rect.center();
+        let zoom = self.main_image_settings.view.zoom;
+        let max_scale = self.active_image_settings.croped_modified.max_scale * image.transform.original_scale * zoom;
         // let min_main_image_size = main_image_rect.width().min(main_image_rect.height());
         let texture = image.texture_handle.as_ref().unwrap();
         // let max_image_size = texture.size()[0].max(texture.size()[1]);
         // let croped = image.transform.croped.unwrap_or_default();
-        let croped = self.active_image_settings.croped_modified.current_modified.unwrap_or_default();
+        let croped = self.active_image_settings.croped_modified.current_modified.unwrap_or_default().scaled(zoom);
         // let texture_props = &image.image_props;
         // Calculate the scaled size while maintaining aspect ratio
-        let img_size = texture.size_vec2();
+        let img_size = image.transform.oriented_size(image.image_size());
         
         // Scale the image based on the board size and scale factor
 
@@ -562,8 +1458,8 @@ impl AppExt for App {
         let scaled_width = img_size.x  * max_scale;
         
         // Center the image in the board
-        let center_x = main_image_rect.min.x + main_image_rect.width() / 2.0;
-        let center_y = main_image_rect.min.y + main_image_rect.height() / 2.0;
+        let center_x = board_center.x;
+        let center_y = board_center.y;
         
         // Apply pan offset
         
@@ -599,12 +1495,13 @@ impl AppExt for App {
         
         // image.image_props.uv_rect = uv_rect;
         // Calculate rotation
-        ui.painter().image(
+        ui.painter().add(oriented_image_mesh(
             texture.id(), 
             background_texture_rect, 
             uv_rect, 
-            Color32::from_white_alpha((0.5 * 255.0) as u8)
-        );
+            Color32::from_white_alpha((0.5 * 255.0) as u8),
+            &image.transform
+        ));
         
         // Clamp the texture rectangle to the board boundaries
         let texture_rect = egui::Rect {
@@ -630,26 +1527,49 @@ impl AppExt for App {
         );
         
         image.image_props.uv_rect = uv_rect;
-        // Calculate rotation
-        ui.painter().image(
+        ui.painter().add(oriented_image_mesh(
             texture.id(), 
             texture_rect, 
             uv_rect, 
-            Color32::from_white_alpha((image.transform.opacity * 255.0) as u8)
-        );
+            Color32::from_white_alpha((image.transform.opacity * 255.0) as u8),
+            &image.transform
+        ));
         
         unclamped_rect
     }
     fn remove_image(&mut self, image_id: TextureId) {
         let find_image = self.output_textures_vec.iter_mut().enumerate().find(|(_index, image)| image.texture_handle.as_ref().unwrap().id() == image_id);
         if let Some((idx, _image)) = find_image {
-            self.output_textures_vec.remove(idx);
-            // image.texture_handle.as_ref().unwrap();
-            if let Some(check_active) = self.active_image.as_ref() {
-                if check_active.get_texture_id() == image_id {
+            let removed = self.output_textures_vec.remove(idx);
+            if let Some(tiles) = removed.tiles.as_ref() {
+                tiles.release_tiles();
+            }
+            let groups = self.layer_groups.clone();
+            // The last layer of a group takes the emptied group along
+            normalize(&mut self.output_textures_vec, &mut self.layer_groups);
+            self.history.push(BoardCommand::Remove { index: idx, texture_id: image_id, texture: Box::new(removed), groups });
+            self.selection.layers.retain(|id| *id != image_id);
+            if let Some(check_active) = self.active_image.as_ref()
+                && check_active.get_texture_id() == image_id {
                     self.active_image = None;
                 }
-            }
+        }
+    }
+    /// Records a panel toggle or a committed rename of the layer at `idx`, if anything changed.
+    fn record_layer_state(&mut self, idx: usize, before: LayerState) {
+        let Some(layer) = self.output_textures_vec.get(idx) else {
+            return;
+        };
+        let after = LayerState::from_layer(layer);
+        if after != before {
+            let texture_id = layer.texture_handle.as_ref().unwrap().id();
+            self.history.push(BoardCommand::LayerState { texture_id, before, after });
+        }
+    }
+    fn evict_unused_tiles(&self, ctx: &egui::Context) {
+        let frame = ctx.cumulative_pass_nr();
+        for tiles in self.output_textures_vec.iter().filter_map(|image| image.tiles.as_ref()) {
+            tiles.evict_unused(frame);
         }
     }
     fn display_sorting_images(&mut self, ctx: &egui::Context ) {
@@ -683,7 +1603,8 @@ impl AppExt for App {
                         Pos2::new(container_rect.min.x + margin + image_rect_size.x, container_rect.min.y + margin + (image_rect_size.y + margin * 2.) * idx  as f32 + image_rect_size.y)
                     );
 
-                    let scaled_size = Vec2::new(image_rect_size.x, image_rect_size.x / image_preview.image_ratio);
+                    let oriented_size = image_preview.transform.oriented_size(image_preview.texture_handle.as_ref().unwrap().size_vec2());
+                    let scaled_size = Vec2::new(image_rect_size.x, image_rect_size.x * oriented_size.y / oriented_size.x);
                     
                     let mut uv_rect = egui::Rect::from_min_max(
                         Pos2::new(0.0, 0.5 - (image_rect_size.y / scaled_size.y) / 2.),
@@ -692,24 +1613,25 @@ impl AppExt for App {
                     
                     if self.image_sorting_modify.is_sorting && self.image_sorting_modify.is_draging {
                         let new_idx = ((self.image_sorting_modify.drag_pos.y - container_rect.min.y ) / (image_rect_size.y + margin * 2.)).round() as usize;
-                        if new_idx > 0. as usize && idx == (new_idx.clone() - 1) {
+                        if new_idx > 0. as usize && idx == (new_idx - 1) {
                             image_rect.max.y -= 5.;
                             uv_rect.max.y -= 5. / scaled_size.y;
                         }
 
-                        if new_idx < (image_vec.len().clone() ) && idx == new_idx.clone()  {
+                        if new_idx < image_vec.len() && idx == new_idx  {
                             image_rect.min.y += 5.;
                             uv_rect.min.y += 5. / scaled_size.y;
 
                         }
                     }
                     // let new_rect_height = image_rect.height();
-                    ui.painter().image(
+                    ui.painter().add(oriented_image_mesh(
                         image_preview.texture_handle.as_ref().unwrap().id(),
                         image_rect,
                         uv_rect,
-                        Color32::from_white_alpha(255)
-                    );
+                        Color32::from_white_alpha(255),
+                        &image_preview.transform
+                    ));
 
 
                 }
@@ -750,19 +1672,19 @@ impl AppExt for App {
                             ctx.set_cursor_icon(CursorIcon::PointingHand);
                             let interact_pos = image_drag_sense.interact_pointer_pos().unwrap() as Pos2;
                            
-                            let mut new_pos = interact_pos.clone().clamp(container_rect.min, container_rect.max);
+                            let mut new_pos = interact_pos.clamp(container_rect.min, container_rect.max);
                             let new_idx = ((new_pos.y - container_rect.min.y ) / (image_rect_size.y + margin * 2.)).round() as usize;
                             new_pos.y = new_idx as f32 * (image_rect_size.y + margin * 2.) + container_rect.min.y; 
                             new_pos.x = new_pos.x.clamp(container_rect.min.x + margin, container_rect.min.x + margin * 2. + image_rect_size.x + 25.);
                            
-                            self.image_sorting_modify.drag_pos = new_pos.clone();
+                            self.image_sorting_modify.drag_pos = new_pos;
                             ui.painter().line(
                                 vec![
                                     Pos2::new(container_rect.min.x + margin * 2. + image_rect_size.x, container_rect.min.y + (image_rect_size.y + margin * 2.) * active_idx as f32 + image_rect_size.y / 2.),
                                     Pos2::new(container_rect.min.x + margin * 2. + image_rect_size.x + 25., container_rect.min.y + (image_rect_size.y + margin * 2.) * active_idx as f32 + image_rect_size.y / 2.),
                                     Pos2::new(container_rect.min.x + margin * 2. + image_rect_size.x + 25., new_pos.y),
                                     
-                                    new_pos.clone()
+                                    new_pos
                                 ], 
                                 Stroke::new(2., Color32::WHITE)
                             );
@@ -782,28 +1704,533 @@ impl AppExt for App {
                             ctx.set_cursor_icon(CursorIcon::Alias);
                             self.image_sorting_modify.is_draging = false;
                             let new_idx = ((self.image_sorting_modify.drag_pos.y - container_rect.min.y ) / (image_rect_size.y + margin * 2.)).round() as usize;
-                            if let Some(current_idx) = self.image_sorting_modify.from_image {
-                                if !(new_idx == current_idx) {
-                                    if new_idx < current_idx {
-                                        let clone_image = image_preview.clone();
+                            if let Some(current_idx) = self.image_sorting_modify.from_image
+                                && (new_idx != current_idx) {
+                                    let dest_idx = if new_idx < current_idx { new_idx } else { new_idx - 1 };
+                                    if dest_idx != current_idx {
+                                        let before = LayerArrangement::capture(image_vec, &self.layer_groups);
+                                        let mut clone_image = image_preview.clone();
+                                        // The layer joins the group of the layer it lands on, so the groups stay in one piece
+                                        clone_image.group = image_vec.get(new_idx).and_then(|below| below.group);
                                         image_vec.remove(current_idx);
-                                        image_vec.insert(new_idx, clone_image);
-                                    } else {
-                                        let clone_image = image_preview.clone();
-                                        image_vec.remove(current_idx);
-                                        image_vec.insert(new_idx - 1, clone_image);
+                                        image_vec.insert(dest_idx, clone_image);
+                                        normalize(image_vec, &mut self.layer_groups);
+                                        let after = LayerArrangement::capture(image_vec, &self.layer_groups);
+                                        self.history.push(BoardCommand::Arrange { before, after });
                                     }
                                 }
-                            }
                         } 
                     }
                 }
                 
             });
     }
+    fn export_board(&mut self) {
+        let file_path: Option<PathBuf> = FileDialog::new()
+            .add_filter("Image", &EXPORT_EXTENSIONS)
+            .set_file_name("board.png")
+            .save_file();
+        if let Some(path) = file_path
+            && let Err(err) = save_board(&resolved_layers(&self.output_textures_vec, &self.layer_groups), self.main_image_settings.board_size, self.main_image_settings.background, &path) {
+            self.error_message = Some(format!("Unable to export board to {}: {err}", path.display()));
+        }
+    }
+    fn save_project(&mut self) {
+        let file_path: Option<PathBuf> = FileDialog::new()
+            .add_filter("Image board project", &[PROJECT_EXTENSION])
+            .set_file_name(format!("board.{PROJECT_EXTENSION}"))
+            .save_file();
+        if let Some(path) = file_path {
+            let saved = ProjectManifest::from_layers(&self.output_textures_vec, &self.layer_groups, egui::Rect::from_min_size(Pos2::ZERO, self.main_image_settings.board_size), self.main_image_settings.background)
+                .and_then(|manifest| write_project(&path, &manifest));
+            if let Err(err) = saved {
+                self.error_message = Some(format!("Unable to save project to {}: {err}", path.display()));
+            }
+        }
+    }
+    fn save_bundle(&mut self) {
+        let file_path: Option<PathBuf> = FileDialog::new()
+            .add_filter("Image board bundle", &[BUNDLE_EXTENSION])
+            .set_file_name(format!("board.{BUNDLE_EXTENSION}"))
+            .save_file();
+        if let Some(path) = file_path
+            && let Err(err) = write_bundle(&path, &self.output_textures_vec, &self.layer_groups, egui::Rect::from_min_size(Pos2::ZERO, self.main_image_settings.board_size), self.main_image_settings.background) {
+            self.error_message = Some(format!("Unable to save bundle to {}: {err}", path.display()));
+        }
+    }
+    fn open_project(&mut self, ctx: &egui::Context) {
+        let file_path: Option<PathBuf> = FileDialog::new()
+            .add_filter("Image board project", &[PROJECT_EXTENSION, BUNDLE_EXTENSION])
+            .pick_file();
+        if let Some(path) = file_path {
+            self.load_project(ctx, path);
+        }
+    }
+    /// Replaces the board with a saved project or bundle, the layers show up once the import queue has decoded them.
+    fn load_project(&mut self, ctx: &egui::Context, path: PathBuf) {
+        let is_bundle = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(BUNDLE_EXTENSION));
+        let opened = if is_bundle {
+            read_bundle(&path).map(|(manifest, layers)| {
+                let layers = layers.into_iter().map(|(layer, bytes)| (layer, Some(bytes))).collect::<Vec<_>>();
+                (manifest, layers)
+            })
+        } else {
+            read_project(&path).and_then(|manifest| {
+                find_missing_images(&manifest)?;
+                let layers = manifest.layers.iter().cloned().map(|layer| (layer, None)).collect::<Vec<_>>();
+                Ok((manifest, layers))
+            })
+        };
+        match opened {
+            Ok((manifest, layers)) => {
+                self.imports.cancel_all();
+                self.output_textures_vec.clear();
+                self.layer_groups.clear();
+                self.image_preview_pads.clear();
+                let viewport_rect = self.main_image_settings.viewport_rect;
+                let board_size = manifest.board_rect.size();
+                self.main_image_settings = MainImageRectSetting { board_size, background: manifest.background, viewport_rect, ..Default::default() };
+                self.main_image_settings.view.fit(viewport_rect, board_size);
+                self.active_image = None;
+                self.active_image_settings = ActiveImageSettings::default();
+                self.selection = BoardSelection::default();
+                self.image_sorting_modify = ImageSortingModify::default();
+                self.history.clear();
+                // The images are decoded on the import workers, so a large project does not freeze the window
+                let max_texture_side = ctx.input(|i| i.max_texture_side) as u32;
+                for (slot, (layer, bytes)) in layers.iter().enumerate() {
+                    match bytes {
+                        Some(bytes) => self.imports.enqueue_bytes(ctx, layer.file_path.clone(), bytes.clone(), ImportPlacement::Restored(slot), max_texture_side),
+                        None => self.imports.enqueue(ctx, layer.file_path.clone(), ImportPlacement::Restored(slot), max_texture_side),
+                    };
+                }
+                self.restoring_project = Some(RestoringProject { restored: vec![None; layers.len()], layers, groups: manifest.groups });
+                self.finish_restoring_project();
+            }
+            Err(err) => {
+                self.error_message = Some(format!("Unable to open project {}: {err}", path.display()));
+            }
+        }
+    }
+    fn display_error_message(&mut self, ctx: &egui::Context) {
+        let Some(message) = self.error_message.clone() else {
+            return;
+        };
+        egui::Window::new("Error")
+            .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .collapsible(false)
+            .resizable(Vec2b::new(false, false))
+            .show(ctx, |ui| {
+                ui.label(message);
+                if ui.button("Ok").clicked() {
+                    self.error_message = None;
+                }
+            });
+    }
+    fn undo(&mut self) {
+        if self.history.undo(&mut self.output_textures_vec, &mut self.layer_groups) {
+            self.sync_active_image_after_history();
+        }
+    }
+    fn redo(&mut self) {
+        if self.history.redo(&mut self.output_textures_vec, &mut self.layer_groups) {
+            self.sync_active_image_after_history();
+        }
+    }
+    fn handle_history_shortcuts(&mut self, ctx: &egui::Context) {
+        // Text fields keep their own undo
+        if ctx.wants_keyboard_input() || self.active_image_settings.croped_modified.is_modifying {
+            return;
+        }
+        // Ctrl+Z also matches with shift held, so redo has to be checked first
+        if ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z))) {
+            self.redo();
+        } else if ctx.input_mut(|i| i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, Key::Z))) {
+            self.undo();
+        }
+    }
+    fn record_history(&mut self, ctx: &egui::Context) {
+        let is_interacting = ctx.input(|i| i.pointer.any_down()) || ctx.wants_keyboard_input();
+        self.history.track_transforms(self.selected_transforms(), is_interacting);
+        self.history.track_mask(self.active_mask(), is_interacting);
+    }
+    fn active_mask(&self) -> Option<(TextureId, LayerMask)> {
+        let active_id = self.active_image.as_ref()?.get_texture_id();
+        let layer = self.output_textures_vec.iter().find(|image| image.texture_handle.as_ref().unwrap().id() == active_id)?;
+        Some((active_id, layer.mask.clone()))
+    }
+    fn apply_orientation(&mut self, op: OrientationOp) {
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        self.active_image_settings.transforms.apply_orientation_op(op);
+        if let Some(image) = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == active_image.get_texture_id()) {
+            image.transform = self.active_image_settings.transforms;
+        }
+    }
+    fn bake_orientation(&mut self) {
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        let texture_id = active_image.get_texture_id();
+        let Some(image) = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == texture_id) else {
+            return;
+        };
+        let Some(before) = LayerPixels::from_layer(image) else {
+            return;
+        };
+        if !before.transform.has_orientation() {
+            return;
+        }
+        let mut baked = (*before.image).clone();
+        if before.transform.flip_horizontal {
+            baked = baked.fliph();
+        }
+        if before.transform.flip_vertical {
+            baked = baked.flipv();
+        }
+        baked = match before.transform.quarter_turns % 4 {
+            1 => baked.rotate90(),
+            2 => baked.rotate180(),
+            3 => baked.rotate270(),
+            _ => baked,
+        };
+        let baked_size = Vec2::new(baked.width() as f32, baked.height() as f32);
+        let baked = Arc::new(baked);
+        let after = LayerPixels {
+            // Quarter turns keep the longer side, so a tiled layer stays tiled
+            tiles: before.tiles.as_ref().map(|_| Arc::new(TiledImage::new(build_levels(&baked)))),
+            image: baked,
+            // The pixels no longer match the source file
+            file_path: None,
+            source_bytes: None,
+            image_ratio: calc_ratio(baked_size),
+            transform: ImageTranforms { size: baked_size, flip_horizontal: false, flip_vertical: false, quarter_turns: 0, ..before.transform },
+        };
+        after.apply_to(image);
+        self.active_image_settings.transforms = after.transform;
+        self.history.reset_tracked_transform(Some((texture_id, after.transform)));
+        self.history.push(BoardCommand::ReplacePixels { texture_id, before: Box::new(before), after: Box::new(after) });
+    }
+    fn bake_crop(&mut self, ctx: &egui::Context) {
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        let texture_id = active_image.get_texture_id();
+        let Some(image) = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == texture_id) else {
+            return;
+        };
+        let Some(before) = LayerPixels::from_layer(image) else {
+            return;
+        };
+        let Some(croped) = before.transform.croped.filter(|croped| *croped != CropRect::default()) else {
+            return;
+        };
+        // The crop lives in the oriented image, the source is cut at the matching spot and keeps its flips and turns
+        let source_size = Vec2::new(before.image.width() as f32, before.image.height() as f32);
+        let oriented_size = before.transform.oriented_size(source_size);
+        let pixel_rect = crop_to_pixel_rect(croped, oriented_size, before.transform.original_scale).intersect(egui::Rect::from_min_size(Pos2::ZERO, oriented_size));
+        let pixel_rect = egui::Rect::from_min_max(pixel_rect.min.round(), pixel_rect.max.round());
+        if pixel_rect.width() < 1. || pixel_rect.height() < 1. {
+            return;
+        }
+        let oriented_uv = egui::Rect::from_min_max((pixel_rect.min.to_vec2() / oriented_size).to_pos2(), (pixel_rect.max.to_vec2() / oriented_size).to_pos2());
+        let source_uv = oriented_to_source_uv_rect(oriented_uv, &before.transform);
+        let source_min = (source_uv.min.to_vec2() * source_size).round();
+        let source_max = (source_uv.max.to_vec2() * source_size).round();
+        let baked = before.image.crop_imm(source_min.x as u32, source_min.y as u32, (source_max.x - source_min.x) as u32, (source_max.y - source_min.y) as u32);
+        let baked_size = Vec2::new(baked.width() as f32, baked.height() as f32);
+        let baked = Arc::new(baked);
+
+        // The croped part stays where it was on the board, only the hidden pixels go away
+        let croped_units = oriented_size * before.transform.original_scale + Vec2::new(croped.right - croped.left, croped.bottom - croped.top);
+        let original_scale = croped_units.x / pixel_rect.width();
+        let croped_center = Vec2::new(croped.left + croped.right, croped.top + croped.bottom) / 2. * before.transform.axis_scale();
+        let pos = before.transform.pos + Rot2::from_angle(before.transform.rotation.to_radians()) * croped_center;
+        let max_texture_side = ctx.input(|i| i.max_texture_side) as u32;
+        let after = LayerPixels {
+            tiles: needs_tiles(baked.width(), baked.height(), max_texture_side).then(|| Arc::new(TiledImage::new(build_levels(&baked)))),
+            image: baked,
+            // The pixels no longer match the source file
+            file_path: None,
+            source_bytes: None,
+            image_ratio: calc_ratio(baked_size),
+            transform: ImageTranforms { size: baked_size, original_scale, pos, croped: None, ..before.transform },
+        };
+        after.apply_to(image);
+        self.active_image_settings.transforms = after.transform;
+        self.history.reset_tracked_transform(Some((texture_id, after.transform)));
+        self.history.push(BoardCommand::ReplacePixels { texture_id, before: Box::new(before), after: Box::new(after) });
+    }
+    fn apply_perspective_crop(&mut self, ctx: &egui::Context) {
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        let texture_id = active_image.get_texture_id();
+        let Some(image) = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == texture_id) else {
+            return;
+        };
+        let Some(before) = LayerPixels::from_layer(image) else {
+            return;
+        };
+        let corners = self.active_image_settings.croped_modified.perspective;
+        if corners == FULL_QUAD || !is_convex_quad(corners) {
+            return;
+        }
+        if self.perspective_crop.is_some() {
+            self.toasts.warning("Wait for the running perspective crop to finish");
+            return;
+        }
+        // The corners are picked on the oriented image, sampling the source at the matching spots gives an upright result
+        let source_size = Vec2::new(before.image.width() as f32, before.image.height() as f32);
+        let oriented_size = before.transform.oriented_size(source_size);
+        let source_quad = corners.map(|uv| (before.transform.oriented_uv(uv).to_vec2() * source_size).to_pos2());
+        let size = rectified_size(corners.map(|uv| (uv.to_vec2() * oriented_size).to_pos2()));
+
+        // The new image sits where the middle of the quad was on the board, at the same pixel size
+        let quad_center = corners.iter().fold(Vec2::ZERO, |sum, uv| sum + uv.to_vec2()) / 4.;
+        let center_offset = (quad_center - Vec2::splat(0.5)) * oriented_size * before.transform.original_scale * before.transform.axis_scale();
+        let pos = before.transform.pos + Rot2::from_angle(before.transform.rotation.to_radians()) * center_offset;
+        let transform = ImageTranforms {
+            pos,
+            croped: None,
+            flip_horizontal: false,
+            flip_vertical: false,
+            quarter_turns: 0,
+            ..before.transform
+        };
+        let max_texture_side = ctx.input(|i| i.max_texture_side) as u32;
+        let job = RectifyJob::spawn(ctx, before.image.clone(), source_quad, size, max_texture_side);
+        self.perspective_crop = Some(PendingPerspectiveCrop { texture_id, before, transform, job });
+    }
+
+    fn receive_perspective_crop(&mut self) {
+        let Some(rectified) = self.perspective_crop.as_ref().and_then(|pending| pending.job.try_take()) else {
+            return;
+        };
+        let PendingPerspectiveCrop { texture_id, before, transform, .. } = self.perspective_crop.take().unwrap();
+        // Undo, a bake or a removal while the thread was busy leaves the result without the pixels it was cut from
+        let Some(image) = self.output_textures_vec.iter_mut().find(|image| image.texture_handle.as_ref().unwrap().id() == texture_id) else {
+            self.toasts.warning("The layer was removed before its perspective crop finished");
+            return;
+        };
+        if !image.image.as_ref().is_some_and(|current| Arc::ptr_eq(current, &before.image)) {
+            self.toasts.warning("The layer changed before its perspective crop finished, the crop was dropped");
+            return;
+        }
+        let rectified_size = Vec2::new(rectified.image.width() as f32, rectified.image.height() as f32);
+        let after = LayerPixels {
+            tiles: rectified.levels.map(|levels| Arc::new(TiledImage::new(levels))),
+            image: rectified.image,
+            // The pixels no longer match the source file
+            file_path: None,
+            source_bytes: None,
+            image_ratio: calc_ratio(rectified_size),
+            // Moves made while the thread was busy are kept
+            transform: ImageTranforms { size: rectified_size, pos: transform.pos + (image.transform.pos - before.transform.pos), ..transform },
+        };
+        after.apply_to(image);
+        if self.active_image.as_ref().is_some_and(|active| active.get_texture_id() == texture_id) {
+            self.active_image_settings.transforms = after.transform;
+        }
+        self.history.reset_tracked_transforms(self.selected_transforms());
+        self.history.push(BoardCommand::ReplacePixels { texture_id, before: Box::new(before), after: Box::new(after) });
+    }
+    fn display_image_info(&mut self, ui: &mut egui::Ui) {
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        let Some(image) = self.output_textures_vec.iter().find(|image| image.texture_handle.as_ref().unwrap().id() == active_image.get_texture_id()) else {
+            return;
+        };
+        let metadata = &image.metadata;
+        let not_available = || "-".to_string();
+        let rows = [
+            ("File", image.file_path.as_ref().and_then(|path| path.file_name()).map(|name| name.to_string_lossy().to_string()).unwrap_or_else(not_available)),
+            ("Format", metadata.format.clone().unwrap_or_else(not_available)),
+            ("Dimensions", format!("{} x {}", metadata.width, metadata.height)),
+            ("Camera", metadata.camera.clone().unwrap_or_else(not_available)),
+            ("Taken", metadata.date_taken.clone().unwrap_or_else(not_available)),
+            ("DPI", metadata.dpi.map(|(x, y)| if x == y { format!("{x:.0}") } else { format!("{x:.0} x {y:.0}") }).unwrap_or_else(not_available)),
+            ("Colour profile", metadata.color_profile.clone().unwrap_or_else(not_available)),
+            ("EXIF orientation", metadata.exif_orientation.map(|orientation| format!("{orientation} (applied on import)")).unwrap_or_else(not_available)),
+        ];
+        egui::Grid::new("image_info_grid").num_columns(2).striped(true).show(ui, |ui| {
+            for (label, value) in rows {
+                ui.label(label);
+                ui.label(value);
+                ui.end_row();
+            }
+        });
+    }
+    fn display_board_settings(&mut self, ui: &mut egui::Ui) {
+        let settings = &mut self.main_image_settings;
+        let mut board_size = settings.board_size;
+        let mut is_resized = false;
+        ui.horizontal(|ui| {
+            let size_range = MIN_BOARD_SIZE..=MAX_BOARD_SIZE;
+            is_resized |= ui.add(egui::DragValue::new(&mut board_size.x).range(size_range.clone()).speed(1.).prefix("W: ").suffix(" px")).changed();
+            is_resized |= ui.add(egui::DragValue::new(&mut board_size.y).range(size_range).speed(1.).prefix("H: ").suffix(" px")).changed();
+            if ui.button(egui_phosphor::regular::ARROWS_LEFT_RIGHT).on_hover_text("Swap width and height").clicked() {
+                board_size = Vec2::new(board_size.y, board_size.x);
+                is_resized = true;
+            }
+        });
+        let preset_name = BOARD_PRESETS.iter().find(|(_, size)| *size == settings.board_size).map_or("Custom", |(name, _)| name);
+        let mut preset_size = None;
+        egui::ComboBox::from_label("Preset").selected_text(preset_name).show_ui(ui, |ui| {
+            for (name, size) in BOARD_PRESETS {
+                if ui.selectable_label(preset_name == name, format!("{name} ({} × {})", size.x, size.y)).clicked() {
+                    preset_size = Some(size);
+                }
+            }
+        });
+        if is_resized {
+            settings.set_board_size(board_size);
+        }
+        // A preset usually changes the size a lot, it is brought fully into view
+        if let Some(size) = preset_size {
+            settings.set_board_size(size);
+            settings.view.fit(settings.viewport_rect, settings.board_size);
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Background");
+            let mut is_transparent = settings.background == BoardBackground::Transparent;
+            if ui.selectable_value(&mut is_transparent, false, "Colour").clicked() && settings.background == BoardBackground::Transparent {
+                settings.background = BoardBackground::default();
+            }
+            if ui.selectable_value(&mut is_transparent, true, "Transparent").clicked() {
+                settings.background = BoardBackground::Transparent;
+            }
+            if let BoardBackground::Color(color) = &mut settings.background {
+                ui.color_edit_button_srgba(color);
+            }
+        });
+    }
+
+    fn push_layer(&mut self, layer: OutputTexture) {
+        // A new layer must not pull the active layer away from a running crop
+        if !self.active_image_settings.croped_modified.is_modifying {
+            self.active_image_settings = ActiveImageSettings::default();
+            self.active_image_settings.transforms = layer.transform;
+            self.active_image = Some(ActiveImage::new(layer.texture_handle.as_ref().unwrap().id()));
+            self.selection.layers = vec![layer.texture_handle.as_ref().unwrap().id()];
+        }
+        self.image_preview_pads.insert(0, ImagePreviewPad { texture: layer.clone() });
+        let texture_id = layer.texture_handle.as_ref().unwrap().id();
+        self.history.push(BoardCommand::Add { index: 0, texture_id, texture: Box::new(layer.clone()) });
+        self.output_textures_vec.insert(0, layer);
+    }
+
+    fn handle_clipboard_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() || self.active_image_settings.croped_modified.is_modifying {
+            return;
+        }
+        let (is_copy, is_paste_pressed, is_paste_released) = ctx.input(|i| {
+            let is_copy = i.events.iter().any(|event| matches!(event, egui::Event::Copy));
+            // egui swallows the paste key press and only reports it as a text paste,
+            // a clipboard holding just an image shows up no earlier than the released V key
+            let is_paste_pressed = i.events.iter().any(|event| matches!(event, egui::Event::Paste(_)));
+            let is_paste_released = i.events.iter().any(|event| matches!(event, egui::Event::Key { key: Key::V, pressed: false, modifiers, .. } if modifiers.command));
+            (is_copy, is_paste_pressed, is_paste_released)
+        });
+        if is_copy {
+            self.copy_active_layer();
+        }
+        if is_paste_pressed {
+            self.is_paste_key_held = true;
+            self.paste_image(ctx);
+        }
+        // The press already pasted when egui reported it
+        if is_paste_released && !std::mem::take(&mut self.is_paste_key_held) {
+            self.paste_image(ctx);
+        }
+    }
+
+    fn paste_image(&mut self, ctx: &egui::Context) {
+        let pasted = match self.clipboard.get_image() {
+            Ok(pasted) => pasted,
+            // Text on the clipboard is not meant for the board
+            Err(ClipboardError::NoImage) => return,
+            Err(err) => {
+                self.toasts.warning(format!("Unable to paste: {err}"));
+                return;
+            }
+        };
+        let image = Arc::new(DynamicImage::ImageRgba8(pasted));
+        let image_size = Vec2::new(image.width() as f32, image.height() as f32);
+        let original_scale = calc_orignal_scale(image_size, self.main_image_settings.board_size);
+        let (texture_handle, tiles) = load_layer_textures(ctx, &image);
+        self.push_layer(OutputTexture {
+            name: "Pasted image".to_string(),
+            metadata: ImageMetadata { width: image.width(), height: image.height(), ..Default::default() },
+            image: Some(image),
+            texture_handle: Some(texture_handle),
+            tiles,
+            original_scale,
+            image_ratio: calc_ratio(image_size),
+            transform: ImageTranforms { size: image_size, original_scale, ..Default::default() },
+            ..Default::default()
+        });
+    }
+
+    fn copy_active_layer(&mut self) {
+        let Some(active_image) = self.active_image.as_ref() else {
+            return;
+        };
+        let Some(layer) = self.output_textures_vec.iter().find(|image| image.texture_handle.as_ref().unwrap().id() == active_image.get_texture_id()) else {
+            return;
+        };
+        let Some(pixels) = layer_image(layer) else {
+            self.toasts.warning("The active layer has nothing left to copy");
+            return;
+        };
+        match self.clipboard.set_image(&pixels) {
+            Ok(()) => self.toasts.info("Copied the layer to the clipboard"),
+            Err(err) => self.toasts.error(format!("Unable to copy the layer: {err}")),
+        }
+    }
+
+    fn copy_board(&mut self) {
+        let board = flatten_board(&resolved_layers(&self.output_textures_vec, &self.layer_groups), self.main_image_settings.board_size, self.main_image_settings.background);
+        match self.clipboard.set_image(&board) {
+            Ok(()) => self.toasts.info("Copied the board to the clipboard"),
+            Err(err) => self.toasts.error(format!("Unable to copy the board: {err}")),
+        }
+    }
+
+    fn sync_active_image_after_history(&mut self) {
+        // A redone removal takes its layer out of the selection as well
+        let layer_ids: Vec<TextureId> = self.output_textures_vec.iter().map(|image| image.texture_handle.as_ref().unwrap().id()).collect();
+        self.selection.layers.retain(|id| layer_ids.contains(id));
+        if let Some(active) = self.active_image.as_ref() {
+            let active_id = active.get_texture_id();
+            match self.output_textures_vec.iter().find(|image| image.texture_handle.as_ref().unwrap().id() == active_id) {
+                Some(image) => {
+                    self.active_image_settings.transforms = image.transform;
+                    self.history.reset_tracked_transforms(self.selected_transforms());
+                }
+                None => {
+                    self.active_image = None;
+                    self.history.reset_tracked_transform(None);
+                }
+            }
+        }
+        self.history.reset_tracked_mask(self.active_mask());
+    }
 }
 
 
+/// Marks where a dragged layer or group lands in the Images panel, a line above the row or a frame around a group.
+pub fn paint_drop_hint(ui: &egui::Ui, rect: egui::Rect, into: bool) {
+    let color = Color32::from_rgb(80, 150, 255);
+    if into {
+        ui.painter().rect_stroke(rect, 5., Stroke::new(2., color), egui::StrokeKind::Inside);
+    } else {
+        ui.painter().hline(rect.x_range(), rect.min.y - 1., Stroke::new(2., color));
+    }
+}
 
 fn calc_ratio(image_size: Vec2) -> f32 {
     
@@ -831,4 +2258,679 @@ pub fn calc_max_scale(image_size: Vec2, plot_size: Vec2) -> f32 {
     } else {
         default_size / image_size.y
     }
-}
\ No newline at end of file
+}
+
+/// Returns the layer rect on the board and the part of it left after croping, both unclamped.
+/// `image_size` is the source size, flips and quarter turns are applied here.
+pub fn calc_layer_rects(image_size: Vec2, transform: &ImageTranforms, board_rect: egui::Rect) -> (egui::Rect, egui::Rect) {
+    let axis_scale = transform.axis_scale();
+    let scaled_size = transform.oriented_size(image_size) * transform.original_scale * axis_scale;
+    let unclamped_rect = egui::Rect::from_center_size(board_rect.center() + transform.pos.to_vec2(), scaled_size);
+    let croped = transform.croped.unwrap_or_default();
+    let croped_rect = egui::Rect {
+        min: unclamped_rect.min + Vec2::new(croped.left, croped.top) * axis_scale,
+        max: unclamped_rect.max + Vec2::new(croped.right, croped.bottom) * axis_scale,
+    };
+    (unclamped_rect, croped_rect)
+}
+
+pub fn load_texture_from_image(ctx: &egui::Context, image: &DynamicImage) -> TextureHandle {
+    let color_image = ColorImage::from_rgba_unmultiplied(
+        [image.width() as _, image.height() as _],
+        image.to_rgba8().as_flat_samples().as_slice(),
+    );
+    load_texture_from_color_image(ctx, color_image)
+}
+
+pub fn load_texture_from_color_image(ctx: &egui::Context, color_image: ColorImage) -> TextureHandle {
+    ctx.load_texture(
+        "imported_image",
+        color_image,
+        TextureOptions::default(),
+    )
+}
+
+/// Moves a freshly decoded layer to its planned spot, grid cells also shrink the layer to fit.
+fn place_imported_layer(transform: &mut ImageTranforms, placement: ImportPlacement) {
+    match placement {
+        ImportPlacement::Center | ImportPlacement::Restored(_) => {}
+        ImportPlacement::At(pos) => transform.pos = pos,
+        ImportPlacement::Cell(cell) => {
+            let display_size = transform.oriented_size(transform.size) * transform.original_scale;
+            let fit_size = cell.size() * 0.9;
+            transform.scale = (fit_size.x / display_size.x).min(fit_size.y / display_size.y).min(1.);
+            transform.pos = cell.center();
+        }
+    }
+}
+
+/// Corners of the croped layer on the board rotated around the image center, clockwise from the top left.
+pub fn calc_layer_corners(image_size: Vec2, transform: &ImageTranforms, board_rect: egui::Rect) -> [Pos2; 4] {
+    let (unclamped_rect, croped_rect) = calc_layer_rects(image_size, transform, board_rect);
+    let rotation = Rot2::from_angle(transform.rotation.to_radians());
+    let center = unclamped_rect.center();
+    [croped_rect.left_top(), croped_rect.right_top(), croped_rect.right_bottom(), croped_rect.left_bottom()]
+        .map(|corner| center + rotation * (corner - center))
+}
+
+pub fn is_point_in_quad(point: Pos2, quad: &[Pos2; 4]) -> bool {
+    let mut side = 0.;
+    for idx in 0..quad.len() {
+        let start = quad[idx];
+        let edge = quad[(idx + 1) % quad.len()] - start;
+        let cross = edge.x * (point.y - start.y) - edge.y * (point.x - start.x);
+        if cross != 0. {
+            if side == 0. {
+                side = cross.signum();
+            } else if cross.signum() != side {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Builds a mesh showing the `uv_rect` part of the flipped and turned image inside `rect`.
+pub fn oriented_image_mesh(texture_id: TextureId, rect: egui::Rect, uv_rect: egui::Rect, tint: Color32, transform: &ImageTranforms) -> egui::Mesh {
+    let mut mesh = egui::Mesh::with_texture(texture_id);
+    let corners = [
+        (rect.left_top(), uv_rect.left_top()),
+        (rect.right_top(), uv_rect.right_top()),
+        (rect.right_bottom(), uv_rect.right_bottom()),
+        (rect.left_bottom(), uv_rect.left_bottom()),
+    ];
+    for (pos, uv) in corners {
+        mesh.vertices.push(egui::epaint::Vertex { pos, uv: transform.oriented_uv(uv), color: tint });
+    }
+    mesh.add_triangle(0, 1, 2);
+    mesh.add_triangle(0, 2, 3);
+    mesh
+}
+
+/// Bounding uv rect in the source texture of a uv rect in the oriented image, flips and quarter turns keep rects axis aligned.
+pub fn oriented_to_source_uv_rect(uv_rect: egui::Rect, transform: &ImageTranforms) -> egui::Rect {
+    egui::Rect::from_two_pos(transform.oriented_uv(uv_rect.min), transform.oriented_uv(uv_rect.max))
+}
+
+pub fn source_to_oriented_uv_rect(source_uv_rect: egui::Rect, transform: &ImageTranforms) -> egui::Rect {
+    egui::Rect::from_two_pos(transform.source_to_oriented_uv(source_uv_rect.min), transform.source_to_oriented_uv(source_uv_rect.max))
+}
+
+pub fn update_texture_from_image(texture_handle: &mut TextureHandle, image: &DynamicImage) {
+    let color_image = ColorImage::from_rgba_unmultiplied(
+        [image.width() as _, image.height() as _],
+        image.to_rgba8().as_flat_samples().as_slice(),
+    );
+    texture_handle.set(color_image, TextureOptions::default());
+}
+
+/// Uploads the layer texture, images too large for one texture only upload their preview and draw from tiles.
+pub fn load_layer_textures(ctx: &egui::Context, image: &Arc<DynamicImage>) -> (TextureHandle, Option<Arc<TiledImage>>) {
+    let max_texture_side = ctx.input(|i| i.max_texture_side) as u32;
+    let (color_image, levels) = prepare_layer_pixels(image, max_texture_side);
+    (load_texture_from_color_image(ctx, color_image), levels.map(|levels| Arc::new(TiledImage::new(levels))))
+}
+
+/// Paints the board background, a transparent board shows a checkerboard of fixed screen size.
+pub fn paint_board_background(painter: &egui::Painter, board_rect: egui::Rect, background: BoardBackground) {
+    let checker_size = 10.;
+    match background {
+        BoardBackground::Color(color) => {
+            painter.rect_filled(board_rect, 0.0, color);
+        }
+        BoardBackground::Transparent => {
+            painter.rect_filled(board_rect, 0.0, Color32::from_gray(204));
+            // Only the squares in the visible part are painted, a zoomed in board can be far larger than the screen
+            let visible_rect = board_rect.intersect(painter.clip_rect());
+            if !visible_rect.is_positive() {
+                return;
+            }
+            let first_column = ((visible_rect.min.x - board_rect.min.x) / checker_size).floor() as i32;
+            let last_column = ((visible_rect.max.x - board_rect.min.x) / checker_size).ceil() as i32;
+            let first_row = ((visible_rect.min.y - board_rect.min.y) / checker_size).floor() as i32;
+            let last_row = ((visible_rect.max.y - board_rect.min.y) / checker_size).ceil() as i32;
+            let mut mesh = egui::Mesh::default();
+            for row in first_row..last_row {
+                for column in first_column..last_column {
+                    if (row + column) % 2 == 0 {
+                        continue;
+                    }
+                    let min = board_rect.min + Vec2::new(column as f32, row as f32) * checker_size;
+                    let square = egui::Rect::from_min_size(min, Vec2::splat(checker_size)).intersect(board_rect);
+                    mesh.add_colored_rect(square, Color32::from_gray(255));
+                }
+            }
+            painter.add(mesh);
+        }
+    }
+}
+
+/// The croped layer in board units around the layer position, before rotation.
+pub fn calc_layer_local_rect(image_size: Vec2, transform: &ImageTranforms) -> egui::Rect {
+    let (_, croped_rect) = calc_layer_rects(image_size, transform, egui::Rect::from_center_size(Pos2::ZERO, Vec2::ZERO));
+    croped_rect.translate(-transform.pos.to_vec2())
+}
+
+fn handle_local_pos(local_rect: egui::Rect, x: i8, y: i8) -> Pos2 {
+    let along = |min: f32, max: f32, side: i8| match side {
+        -1 => min,
+        1 => max,
+        _ => (min + max) / 2.,
+    };
+    Pos2::new(along(local_rect.min.x, local_rect.max.x, x), along(local_rect.min.y, local_rect.max.y, y))
+}
+
+/// Resize cursor closest to the direction the handle points at on screen.
+fn resize_cursor(direction: Vec2) -> CursorIcon {
+    let octant = (direction.angle().to_degrees().rem_euclid(180.) / 45.).round() as i32 % 4;
+    match octant {
+        0 => CursorIcon::ResizeHorizontal,
+        1 => CursorIcon::ResizeNwSe,
+        2 => CursorIcon::ResizeVertical,
+        _ => CursorIcon::ResizeNeSw,
+    }
+}
+
+/// Scales the layer so the dragged handle follows `pointer` while the opposite handle, or the center when
+/// `about_center` is set, stays in place. Edge handles only stretch their own axis unless `keep_aspect` is set.
+pub fn scale_with_handle(image_size: Vec2, start: &ImageTranforms, x: i8, y: i8, pointer: Pos2, keep_aspect: bool, about_center: bool) -> ImageTranforms {
+    let rotation = Rot2::from_angle(start.rotation.to_radians());
+    let axis_scale = start.axis_scale();
+    // The croped rect before any scale, so the fixed point keeps its place in the image whatever the scale
+    let local_rect = calc_layer_local_rect(image_size, start);
+    let unit_rect = egui::Rect::from_min_max((local_rect.min.to_vec2() / axis_scale).to_pos2(), (local_rect.max.to_vec2() / axis_scale).to_pos2());
+    let handle = handle_local_pos(unit_rect, x, y);
+    let fixed = if about_center { unit_rect.center() } else { handle_local_pos(unit_rect, -x, -y) };
+    let fixed_on_board = start.pos + rotation * (fixed.to_vec2() * axis_scale);
+    let reach = rotation.inverse() * (pointer - fixed_on_board);
+    let handle_reach = (handle - fixed) * axis_scale;
+
+    let ratio = |reach: f32, handle_reach: f32| if handle_reach.abs() > f32::EPSILON { reach / handle_reach } else { 1. };
+    let mut factor = Vec2::new(
+        if x != 0 { ratio(reach.x, handle_reach.x) } else { 1. },
+        if y != 0 { ratio(reach.y, handle_reach.y) } else { 1. },
+    );
+    if keep_aspect {
+        let uniform = if x != 0 && y != 0 { reach.dot(handle_reach) / handle_reach.length_sq().max(f32::EPSILON) } else if x != 0 { factor.x } else { factor.y };
+        factor = Vec2::splat(uniform);
+    }
+    let new_axis_scale = (axis_scale * factor).max(Vec2::splat(MIN_LAYER_SCALE * 0.1));
+    let scale = new_axis_scale.y.clamp(MIN_LAYER_SCALE, MAX_LAYER_SCALE);
+    let aspect_stretch = (new_axis_scale.x / scale).clamp(MIN_LAYER_SCALE / MAX_LAYER_SCALE, MAX_LAYER_SCALE / MIN_LAYER_SCALE);
+    let scaled = ImageTranforms { scale, aspect_stretch, ..*start };
+    let pos = fixed_on_board - rotation * (fixed.to_vec2() * scaled.axis_scale());
+    ImageTranforms { pos, ..scaled }
+}
+
+/// Moves and scales the `followers` of a group selection along with the active layer going from `previous` to `current`.
+/// The scale and stretch ratios are clamped once for the whole group, so the layout keeps its proportions at the limits,
+/// and the active transform is returned as clamped. Stretching happens along the active layer axes.
+pub fn follow_active_layer(previous: &ImageTranforms, current: &ImageTranforms, followers: &mut [&mut ImageTranforms]) -> ImageTranforms {
+    let rotation = Rot2::from_angle(previous.rotation.to_radians());
+    let requested = current.axis_scale() / previous.axis_scale();
+    let requested_stretch = requested.x / requested.y;
+    // A ratio of 1 is always allowed, so a layer already past a limit only holds the others in place
+    let members: Vec<(f32, f32)> = std::iter::once(previous).chain(followers.iter().map(|transform| &**transform)).map(|transform| (transform.scale, transform.aspect_stretch)).collect();
+    let (min_stretch, max_stretch) = (MIN_LAYER_SCALE / MAX_LAYER_SCALE, MAX_LAYER_SCALE / MIN_LAYER_SCALE);
+    let scale_ratio = requested.y.clamp(
+        members.iter().map(|(scale, _)| MIN_LAYER_SCALE / scale).fold(0., f32::max).min(1.),
+        members.iter().map(|(scale, _)| MAX_LAYER_SCALE / scale).fold(f32::MAX, f32::min).max(1.),
+    );
+    let stretch_ratio = requested_stretch.clamp(
+        members.iter().map(|(_, stretch)| min_stretch / stretch).fold(0., f32::max).min(1.),
+        members.iter().map(|(_, stretch)| max_stretch / stretch).fold(f32::MAX, f32::min).max(1.),
+    );
+    let ratio = Vec2::new(scale_ratio * stretch_ratio, scale_ratio);
+
+    let mut active = *current;
+    if scale_ratio != requested.y || stretch_ratio != requested_stretch {
+        active.scale = previous.scale * scale_ratio;
+        active.aspect_stretch = previous.aspect_stretch * stretch_ratio;
+        // Keeps the point the active layer was scaled about in place
+        let start = rotation.inverse() * previous.pos.to_vec2();
+        let end = rotation.inverse() * current.pos.to_vec2();
+        let along = |start: f32, end: f32, requested: f32, ratio: f32| {
+            if (requested - 1.).abs() > f32::EPSILON {
+                let fixed = (end - requested * start) / (1. - requested);
+                fixed + (start - fixed) * ratio
+            } else {
+                end
+            }
+        };
+        active.pos = (rotation * Vec2::new(along(start.x, end.x, requested.x, ratio.x), along(start.y, end.y, requested.y, ratio.y))).to_pos2();
+    }
+    for transform in followers.iter_mut() {
+        let offset = rotation.inverse() * (transform.pos - previous.pos);
+        transform.pos = active.pos + rotation * (offset * ratio);
+        transform.scale *= scale_ratio;
+        transform.aspect_stretch *= stretch_ratio;
+    }
+    active
+}
+
+/// Turns the layer around its center by the angle the pointer swept since the drag started,
+/// `snap` rounds the result to steps of 15 degrees.
+pub fn rotate_with_handle(start: &ImageTranforms, start_pointer: Pos2, pointer: Pos2, snap: bool) -> ImageTranforms {
+    let swept = (pointer - start.pos).angle() - (start_pointer - start.pos).angle();
+    let mut rotation = start.rotation + swept.to_degrees();
+    if snap {
+        rotation = (rotation / 15.).round() * 15.;
+    }
+    // Same range as the rotation slider
+    rotation = (rotation + 180.).rem_euclid(360.) - 180.;
+    ImageTranforms { rotation, ..*start }
+}
+
+/// Moves the dragged crop edges by `delta`, or the whole crop when no handle is given, keeping it inside the image.
+/// Offsets are measured from the image edges, left and top grow inwards while right and bottom are negative.
+/// With an `aspect` the crop keeps that width over height ratio around the side opposite to the handle.
+fn crop_with_handle(start: CropRect, image_size: Vec2, handle: Option<(i8, i8)>, delta: Vec2, aspect: Option<f32>) -> CropRect {
+    let mut croped = start;
+    match handle {
+        None => {
+            let delta = Vec2::new(delta.x.clamp(-start.left, -start.right), delta.y.clamp(-start.top, -start.bottom));
+            croped.left += delta.x;
+            croped.right += delta.x;
+            croped.top += delta.y;
+            croped.bottom += delta.y;
+        }
+        Some((x, y)) => {
+            let max_width = image_size.x - MIN_CROP_SIZE;
+            let max_height = image_size.y - MIN_CROP_SIZE;
+            match x {
+                -1 => croped.left = (start.left + delta.x).clamp(0., (max_width + start.right).max(0.)),
+                1 => croped.right = (start.right + delta.x).clamp((start.left - max_width).min(0.), 0.),
+                _ => {}
+            }
+            match y {
+                -1 => croped.top = (start.top + delta.y).clamp(0., (max_height + start.bottom).max(0.)),
+                1 => croped.bottom = (start.bottom + delta.y).clamp((start.top - max_height).min(0.), 0.),
+                _ => {}
+            }
+            if let Some(ratio) = aspect {
+                let bounds = egui::Rect::from_min_size(Pos2::ZERO, image_size);
+                let locked = lock_crop_aspect(crop_to_pixel_rect(croped, image_size, 1.), bounds, ratio, x, y);
+                croped = crop_from_pixel_rect(locked, image_size, 1.);
+            }
+        }
+    }
+    croped
+}
+
+/// Resizes `rect` to `ratio` while the side opposite to the dragged handle stays put, centered handles keep the center.
+/// The dragged axis leads, corners follow the width, and the result is shrunk until it fits into `bounds`.
+fn lock_crop_aspect(rect: egui::Rect, bounds: egui::Rect, ratio: f32, x: i8, y: i8) -> egui::Rect {
+    let anchor_along = |min: f32, max: f32, side: i8| match side {
+        -1 => max,
+        1 => min,
+        _ => (min + max) / 2.,
+    };
+    let room_along = |anchor: f32, bound_min: f32, bound_max: f32, side: i8| match side {
+        -1 => anchor - bound_min,
+        1 => bound_max - anchor,
+        _ => (anchor - bound_min).min(bound_max - anchor) * 2.,
+    };
+    let anchor = Pos2::new(anchor_along(rect.min.x, rect.max.x, x), anchor_along(rect.min.y, rect.max.y, y));
+    let room = Vec2::new(room_along(anchor.x, bounds.min.x, bounds.max.x, x), room_along(anchor.y, bounds.min.y, bounds.max.y, y));
+    let mut size = if x != 0 { Vec2::new(rect.width(), rect.width() / ratio) } else { Vec2::new(rect.height() * ratio, rect.height()) };
+    size *= (room.x / size.x).min(room.y / size.y).min(1.);
+    let span_along = |anchor: f32, length: f32, side: i8| match side {
+        -1 => (anchor - length, anchor),
+        1 => (anchor, anchor + length),
+        _ => (anchor - length / 2., anchor + length / 2.),
+    };
+    let (min_x, max_x) = span_along(anchor.x, size.x, x);
+    let (min_y, max_y) = span_along(anchor.y, size.y, y);
+    egui::Rect::from_min_max(Pos2::new(min_x, min_y), Pos2::new(max_x, max_y))
+}
+
+/// The largest `ratio` rect inside `bounds`, centered on `rect` as far as the bounds allow.
+fn fit_crop_aspect(rect: egui::Rect, bounds: egui::Rect, ratio: f32) -> egui::Rect {
+    let width = bounds.width().min(bounds.height() * ratio);
+    let size = Vec2::new(width, width / ratio);
+    let center = rect.center().clamp(bounds.min + size / 2., bounds.max - size / 2.);
+    egui::Rect::from_center_size(center, size)
+}
+
+/// The crop as a rect in image pixels, `unit` is the size of one pixel in the units of the crop offsets.
+pub fn crop_to_pixel_rect(croped: CropRect, image_size: Vec2, unit: f32) -> egui::Rect {
+    egui::Rect::from_min_max(
+        (Vec2::new(croped.left, croped.top) / unit).to_pos2(),
+        (image_size + Vec2::new(croped.right, croped.bottom) / unit).to_pos2(),
+    )
+}
+
+pub fn crop_from_pixel_rect(rect: egui::Rect, image_size: Vec2, unit: f32) -> CropRect {
+    CropRect {
+        top: rect.min.y * unit,
+        left: rect.min.x * unit,
+        bottom: (rect.max.y - image_size.y) * unit,
+        right: (rect.max.x - image_size.x) * unit,
+    }
+}
+
+/// Whether a point inside the layer quad also falls inside its mask.
+pub fn is_point_in_mask(point: Pos2, quad: &[Pos2; 4], mask: &LayerMask) -> bool {
+    if *mask == LayerMask::Rectangle {
+        return true;
+    }
+    let (top, left) = (quad[1] - quad[0], quad[3] - quad[0]);
+    let uv = Pos2::new((point - quad[0]).dot(top) / top.length_sq(), (point - quad[0]).dot(left) / left.length_sq());
+    let size = Vec2::new(top.length(), left.length());
+    mask.contains(uv, &mask.outline(size))
+}
+
+/// Triangles covering the mask of a layer whose croped part is `croped_rect`, before rotation.
+pub fn calc_mask_triangles(mask: &LayerMask, croped_rect: egui::Rect) -> Vec<[Pos2; 3]> {
+    let outline: Vec<Pos2> = mask.outline(croped_rect.size()).into_iter().map(|uv| croped_rect.min + uv.to_vec2() * croped_rect.size()).collect();
+    triangulate_polygon(&outline).into_iter().map(|[a, b, c]| [outline[a], outline[b], outline[c]]).collect()
+}
+
+/// Ear clipping, freeform masks can be concave. Whatever is left of a self crossing outline is fanned.
+fn triangulate_polygon(points: &[Pos2]) -> Vec<[usize; 3]> {
+    let cross = |a: Pos2, b: Pos2, c: Pos2| (b - a).x * (c - b).y - (b - a).y * (c - b).x;
+    let area: f32 = (0..points.len()).map(|idx| {
+        let (a, b) = (points[idx], points[(idx + 1) % points.len()]);
+        a.x * b.y - b.x * a.y
+    }).sum();
+    let winding = area.signum();
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len().saturating_sub(2));
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count).find(|&idx| {
+            let (a, b, c) = (remaining[(idx + count - 1) % count], remaining[idx], remaining[(idx + 1) % count]);
+            if cross(points[a], points[b], points[c]) * winding <= 0. {
+                return false;
+            }
+            !remaining.iter().any(|&other| {
+                other != a && other != b && other != c
+                    && cross(points[a], points[b], points[other]) * winding > 0.
+                    && cross(points[b], points[c], points[other]) * winding > 0.
+                    && cross(points[c], points[a], points[other]) * winding > 0.
+            })
+        });
+        let Some(idx) = ear else {
+            break;
+        };
+        triangles.push([remaining[(idx + count - 1) % count], remaining[idx], remaining[(idx + 1) % count]]);
+        remaining.remove(idx);
+    }
+    for idx in 1..remaining.len().saturating_sub(1) {
+        triangles.push([remaining[0], remaining[idx], remaining[idx + 1]]);
+    }
+    triangles
+}
+
+/// Cuts a convex polygon to the part inside `rect`.
+fn clip_polygon_to_rect(polygon: &[Pos2], rect: egui::Rect) -> Vec<Pos2> {
+    let mut clipped = polygon.to_vec();
+    // Each side of the rect as the coordinate it limits, its bound and whether points must stay below it
+    let sides = [(0, rect.min.x, false), (0, rect.max.x, true), (1, rect.min.y, false), (1, rect.max.y, true)];
+    for (axis, bound, is_max) in sides {
+        let is_inside = |point: Pos2| if is_max { point[axis] <= bound } else { point[axis] >= bound };
+        let input = std::mem::take(&mut clipped);
+        for idx in 0..input.len() {
+            let (start, end) = (input[idx], input[(idx + 1) % input.len()]);
+            if is_inside(start) {
+                clipped.push(start);
+            }
+            if is_inside(start) != is_inside(end) {
+                let t = (bound - start[axis]) / (end[axis] - start[axis]);
+                clipped.push(start + (end - start) * t);
+            }
+        }
+    }
+    clipped
+}
+
+/// Mesh of the mask triangles inside `piece_rect`, textured by a texture that covers `source_uv_rect` of the source image.
+pub fn masked_image_mesh(
+    texture_id: TextureId,
+    triangles: &[[Pos2; 3]],
+    piece_rect: egui::Rect,
+    unclamped_rect: egui::Rect,
+    source_uv_rect: egui::Rect,
+    tint: Color32,
+    transform: &ImageTranforms,
+) -> egui::Mesh {
+    let mut mesh = egui::Mesh::with_texture(texture_id);
+    for triangle in triangles {
+        let piece = clip_polygon_to_rect(triangle, piece_rect);
+        if piece.len() < 3 {
+            continue;
+        }
+        let first = mesh.vertices.len() as u32;
+        for pos in piece.iter() {
+            let oriented_uv = ((*pos - unclamped_rect.min) / unclamped_rect.size()).to_pos2();
+            let uv = ((transform.oriented_uv(oriented_uv) - source_uv_rect.min) / source_uv_rect.size()).to_pos2();
+            mesh.vertices.push(egui::epaint::Vertex { pos: *pos, uv, color: tint });
+        }
+        for idx in 1..piece.len() as u32 - 1 {
+            mesh.add_triangle(first, first + idx, first + idx + 1);
+        }
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    const IMAGE_SIZE: Vec2 = Vec2::new(200., 100.);
+
+    fn rotated_layer() -> ImageTranforms {
+        ImageTranforms { size: IMAGE_SIZE, pos: Pos2::new(40., -20.), rotation: 30., ..Default::default() }
+    }
+
+    fn corners(transform: &ImageTranforms) -> [Pos2; 4] {
+        calc_layer_corners(IMAGE_SIZE, transform, egui::Rect::from_center_size(Pos2::ZERO, Vec2::splat(1000.)))
+    }
+
+    fn assert_near(a: Pos2, b: Pos2) {
+        assert!((a - b).length() < 1e-3, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn a_corner_drag_keeps_the_opposite_corner_in_place() {
+        let start = rotated_layer();
+        let [top_left, _, bottom_right, _] = corners(&start);
+        let pointer = bottom_right + Vec2::new(30., 45.);
+        let scaled = scale_with_handle(IMAGE_SIZE, &start, 1, 1, pointer, false, false);
+        assert_near(corners(&scaled)[0], top_left);
+        assert_near(corners(&scaled)[2], pointer);
+    }
+
+    #[test]
+    fn an_edge_drag_only_stretches_its_own_axis() {
+        let start = rotated_layer();
+        let [top_left, top_right, bottom_right, bottom_left] = corners(&start);
+        let right_edge = (top_right + bottom_right.to_vec2()) / 2.;
+        let outwards = (top_right - top_left).normalized();
+        let scaled = scale_with_handle(IMAGE_SIZE, &start, 1, 0, right_edge + outwards * 50., false, false);
+        assert_eq!(scaled.scale, start.scale);
+        assert!((scaled.aspect_stretch - 1.25).abs() < 1e-4, "{}", scaled.aspect_stretch);
+        assert_near(corners(&scaled)[0], top_left);
+        assert_near(corners(&scaled)[3], bottom_left);
+    }
+
+    #[test]
+    fn keep_aspect_scales_both_axes_alike() {
+        let start = ImageTranforms { aspect_stretch: 1.5, ..rotated_layer() };
+        let [top_left, _, bottom_right, _] = corners(&start);
+        let scaled = scale_with_handle(IMAGE_SIZE, &start, 1, 1, bottom_right + Vec2::new(80., -10.), true, false);
+        assert!(scaled.scale > start.scale);
+        assert_eq!(scaled.aspect_stretch, start.aspect_stretch);
+        assert_near(corners(&scaled)[0], top_left);
+    }
+
+    #[test]
+    fn about_center_keeps_the_center_in_place() {
+        let start = rotated_layer();
+        let bottom_right = corners(&start)[2];
+        let scaled = scale_with_handle(IMAGE_SIZE, &start, 1, 1, bottom_right + (bottom_right - start.pos), false, true);
+        assert_near(scaled.pos, start.pos);
+        assert!((scaled.scale - 2.).abs() < 1e-4 && (scaled.aspect_stretch - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn the_scale_is_clamped() {
+        let start = rotated_layer();
+        let scaled = scale_with_handle(IMAGE_SIZE, &start, 1, 1, start.pos, true, true);
+        assert_eq!(scaled.scale, MIN_LAYER_SCALE);
+    }
+
+    #[test]
+    fn an_edge_drag_stretches_the_whole_group() {
+        let previous = ImageTranforms { size: IMAGE_SIZE, ..Default::default() };
+        let current = ImageTranforms { aspect_stretch: 2., pos: Pos2::new(50., 0.), ..previous };
+        let mut follower = ImageTranforms { pos: Pos2::new(100., 40.), ..previous };
+        let active = follow_active_layer(&previous, &current, &mut [&mut follower]);
+        assert_eq!(active, current);
+        assert_near(follower.pos, Pos2::new(250., 40.));
+        assert_eq!((follower.scale, follower.aspect_stretch), (1., 2.));
+    }
+
+    #[test]
+    fn the_group_scale_is_clamped_once_for_every_layer() {
+        let previous = ImageTranforms { size: IMAGE_SIZE, ..Default::default() };
+        // Scaled 4 times about x = -10, the follower can only grow twice as large
+        let current = ImageTranforms { scale: 4., pos: Pos2::new(30., 0.), ..previous };
+        let mut follower = ImageTranforms { scale: MAX_LAYER_SCALE / 2., pos: Pos2::new(100., 0.), ..previous };
+        let active = follow_active_layer(&previous, &current, &mut [&mut follower]);
+        assert!((active.scale - 2.).abs() < 1e-4 && (follower.scale - MAX_LAYER_SCALE).abs() < 1e-4);
+        assert_near(active.pos, Pos2::new(10., 0.));
+        assert_near(follower.pos, Pos2::new(210., 0.));
+    }
+
+    #[test]
+    fn hidden_and_locked_layers_do_not_follow_the_group() {
+        let ctx = egui::Context::default();
+        let mut app = App::default();
+        for name in ["hidden", "locked", "follower", "active"] {
+            app.push_layer(test_layer(&ctx, name));
+        }
+        app.selection.layers = app.output_textures_vec.iter().map(layer_id).collect();
+        app.output_textures_vec[3].is_visible = false;
+        app.output_textures_vec[2].is_locked = true;
+        app.active_image_settings.transforms.pos = Pos2::new(20., 0.);
+        app.update_images_by_active_image();
+        let positions: Vec<_> = app.output_textures_vec.iter().map(|layer| layer.transform.pos.x).collect();
+        assert_eq!(positions, [20., 20., 0., 0.]);
+    }
+
+    #[test]
+    fn removing_the_last_layer_of_a_group_drops_the_group_until_undone() {
+        let ctx = egui::Context::default();
+        let mut app = App::default();
+        for name in ["a", "b"] {
+            app.push_layer(test_layer(&ctx, name));
+        }
+        let ids: Vec<_> = app.output_textures_vec.iter().map(layer_id).collect();
+        app.selection.layers = ids.clone();
+        app.group_selected_layers();
+        let group_id = app.layer_groups[0].id;
+        app.remove_image(ids[0]);
+        assert_eq!(app.layer_groups.len(), 1);
+        app.remove_image(ids[1]);
+        assert!(app.layer_groups.is_empty());
+
+        app.undo();
+        assert_eq!(app.layer_groups.iter().map(|group| group.id).collect::<Vec<_>>(), [group_id]);
+        assert_eq!(app.output_textures_vec[0].group, Some(group_id));
+        app.redo();
+        assert!(app.layer_groups.is_empty() && app.output_textures_vec.is_empty());
+        app.undo();
+        app.undo();
+        assert_eq!(layer_names(&app.output_textures_vec), ["b", "a"]);
+        assert!(app.output_textures_vec.iter().all(|layer| layer.group == Some(group_id)));
+    }
+
+    #[test]
+    fn hiding_a_group_can_be_undone() {
+        let ctx = egui::Context::default();
+        let mut app = App::default();
+        for name in ["a", "b"] {
+            app.push_layer(test_layer(&ctx, name));
+        }
+        app.selection.layers = app.output_textures_vec.iter().map(layer_id).collect();
+        app.group_selected_layers();
+        let group_id = app.layer_groups[0].id;
+        app.edit_layer_group(group_id, |group| group.is_visible = false);
+        assert!(!is_group_visible(&app.layer_groups, group_id));
+        app.undo();
+        assert!(is_group_visible(&app.layer_groups, group_id));
+        app.redo();
+        assert!(!is_group_visible(&app.layer_groups, group_id));
+    }
+
+    #[test]
+    fn an_opened_project_is_decoded_in_the_background_and_keeps_its_order() {
+        let ctx = egui::Context::default();
+        let dir = scratch_dir("open_project");
+        let mut layers = Vec::new();
+        for (name, width) in [("top", 3), ("middle", 5), ("bottom", 7)] {
+            let file_path = dir.join(format!("{name}.png"));
+            gradient_image(width, 2).save(&file_path).unwrap();
+            layers.push(OutputTexture { file_path: Some(file_path), group: (name != "bottom").then_some(1), ..test_layer(&ctx, name) });
+        }
+        layers[1].transform.pos = Pos2::new(30., 40.);
+        let groups = vec![LayerGroup::new(1, None)];
+        let project_path = dir.join(format!("board.{PROJECT_EXTENSION}"));
+        let manifest = ProjectManifest::from_layers(&layers, &groups, egui::Rect::from_min_size(Pos2::ZERO, Vec2::new(400., 300.)), BoardBackground::default()).unwrap();
+        write_project(&project_path, &manifest).unwrap();
+
+        let mut app = App::default();
+        app.push_layer(test_layer(&ctx, "replaced"));
+        app.load_project(&ctx, project_path);
+        assert!(app.output_textures_vec.is_empty() && app.restoring_project.is_some());
+        let started = std::time::Instant::now();
+        while app.restoring_project.is_some() {
+            assert!(started.elapsed().as_secs() < 10, "the project never finished loading");
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            app.receive_imports(&ctx);
+        }
+        assert_eq!(layer_names(&app.output_textures_vec), ["top", "middle", "bottom"]);
+        let widths: Vec<_> = app.output_textures_vec.iter().map(|layer| layer.image.as_ref().unwrap().width()).collect();
+        assert_eq!(widths, [3, 5, 7]);
+        assert_eq!(app.output_textures_vec[1].transform.pos, Pos2::new(30., 40.));
+        assert_eq!(app.layer_groups, groups);
+        assert_eq!(app.main_image_settings.board_size, Vec2::new(400., 300.));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_perspective_crop_is_applied_when_its_thread_is_done() {
+        let ctx = egui::Context::default();
+        let mut app = App::default();
+        app.push_layer(test_image_layer(&ctx, "scan", gradient_image(80, 60)));
+        app.active_image_settings.croped_modified.perspective = [Pos2::new(0.1, 0.1), Pos2::new(0.9, 0.2), Pos2::new(0.8, 0.9), Pos2::new(0.2, 0.8)];
+        app.apply_perspective_crop(&ctx);
+        assert!(app.perspective_crop.is_some());
+
+        let started = std::time::Instant::now();
+        while app.perspective_crop.is_some() {
+            assert!(started.elapsed().as_secs() < 10, "the perspective crop never finished");
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            app.receive_perspective_crop();
+        }
+        let layer = &app.output_textures_vec[0];
+        let image = layer.image.as_ref().unwrap();
+        assert_eq!(layer.transform.size, Vec2::new(image.width() as f32, image.height() as f32));
+        assert!(image.width() < 80 && image.height() < 60);
+        app.undo();
+        assert_eq!(app.output_textures_vec[0].image.as_ref().unwrap().width(), 80);
+    }
+
+    #[test]
+    fn rotation_follows_the_swept_angle_and_snaps_to_15_degrees() {
+        let start = ImageTranforms { pos: Pos2::new(10., 10.), rotation: 5., ..Default::default() };
+        let start_pointer = start.pos + Vec2::new(100., 0.);
+        let pointer = start.pos + Vec2::angled(22_f32.to_radians()) * 100.;
+        assert!((rotate_with_handle(&start, start_pointer, pointer, false).rotation - 27.).abs() < 1e-3);
+        assert_eq!(rotate_with_handle(&start, start_pointer, pointer, true).rotation, 30.);
+        let pointer = start.pos + Vec2::angled(190_f32.to_radians()) * 100.;
+        assert_eq!(rotate_with_handle(&start, start_pointer, pointer, true).rotation, -165.);
+    }
+}
@@ -0,0 +1,173 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use image::RgbaImage;
+
+#[derive(Debug)]
+pub enum ClipboardError {
+    NoImage,
+    InvalidImage,
+    Unavailable(String),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardError::NoImage => write!(f, "the clipboard holds no image"),
+            ClipboardError::InvalidImage => write!(f, "the clipboard image has an invalid size"),
+            ClipboardError::Unavailable(err) => write!(f, "the clipboard is not available: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ClipboardError {}
+
+/// Image access to a clipboard, the system one in the app and an in-memory one where none is available.
+pub trait ClipboardBackend {
+    fn get_image(&mut self) -> Result<RgbaImage, ClipboardError>;
+    fn set_image(&mut self, image: &RgbaImage) -> Result<(), ClipboardError>;
+}
+
+pub struct SystemClipboard {
+    clipboard: arboard::Clipboard,
+}
+
+impl SystemClipboard {
+    pub fn new() -> Result<Self, ClipboardError> {
+        let clipboard = arboard::Clipboard::new().map_err(|err| ClipboardError::Unavailable(err.to_string()))?;
+        Ok(Self { clipboard })
+    }
+}
+
+impl ClipboardBackend for SystemClipboard {
+    fn get_image(&mut self) -> Result<RgbaImage, ClipboardError> {
+        let image = self.clipboard.get_image().map_err(|err| match err {
+            arboard::Error::ContentNotAvailable => ClipboardError::NoImage,
+            err => ClipboardError::Unavailable(err.to_string()),
+        })?;
+        RgbaImage::from_raw(image.width as u32, image.height as u32, image.bytes.into_owned()).ok_or(ClipboardError::InvalidImage)
+    }
+
+    fn set_image(&mut self, image: &RgbaImage) -> Result<(), ClipboardError> {
+        let image_data = arboard::ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: Cow::Borrowed(image.as_raw()),
+        };
+        self.clipboard.set_image(image_data).map_err(|err| ClipboardError::Unavailable(err.to_string()))
+    }
+}
+
+/// Keeps the copied image inside the app.
+#[derive(Default)]
+pub struct MemoryClipboard {
+    image: Option<RgbaImage>,
+}
+
+impl ClipboardBackend for MemoryClipboard {
+    fn get_image(&mut self) -> Result<RgbaImage, ClipboardError> {
+        self.image.clone().ok_or(ClipboardError::NoImage)
+    }
+
+    fn set_image(&mut self, image: &RgbaImage) -> Result<(), ClipboardError> {
+        self.image = Some(image.clone());
+        Ok(())
+    }
+}
+
+/// The clipboard used by the app, it connects to the system clipboard on first use
+/// and falls back to an in-memory one when there is none, so copy and paste still work within the board.
+#[derive(Default)]
+pub struct Clipboard {
+    backend: Option<Box<dyn ClipboardBackend>>,
+}
+
+impl Clipboard {
+    /// A clipboard on the given backend instead of the system one.
+    pub fn with_backend(backend: Box<dyn ClipboardBackend>) -> Self {
+        Self { backend: Some(backend) }
+    }
+
+    fn backend(&mut self) -> &mut dyn ClipboardBackend {
+        self.backend
+            .get_or_insert_with(|| match SystemClipboard::new() {
+                Ok(clipboard) => Box::new(clipboard),
+                Err(err) => {
+                    log::warn!("{err}, copied images stay inside the app");
+                    Box::new(MemoryClipboard::default())
+                }
+            })
+            .as_mut()
+    }
+
+    pub fn get_image(&mut self) -> Result<RgbaImage, ClipboardError> {
+        self.backend().get_image()
+    }
+
+    pub fn set_image(&mut self, image: &RgbaImage) -> Result<(), ClipboardError> {
+        self.backend().set_image(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::Vec2;
+
+    use super::*;
+    use crate::app_ext::{App, AppExt};
+    use crate::export::layer_image;
+    use crate::resource::*;
+    use crate::test_util::*;
+
+    fn memory_clipboard() -> Clipboard {
+        Clipboard::with_backend(Box::new(MemoryClipboard::default()))
+    }
+
+    #[test]
+    fn an_empty_clipboard_has_no_image() {
+        assert!(matches!(memory_clipboard().get_image(), Err(ClipboardError::NoImage)));
+    }
+
+    #[test]
+    fn a_croped_and_masked_layer_is_copied_as_seen() {
+        let ctx = egui::Context::default();
+        let mut layer = test_image_layer(&ctx, "layer", gradient_image(40, 20));
+        layer.transform.croped = Some(CropRect { top: 2., left: 5., bottom: -3., right: -10. });
+        layer.mask = LayerMask::Ellipse;
+        let mut clipboard = memory_clipboard();
+        clipboard.set_image(&layer_image(&layer).unwrap()).unwrap();
+
+        let copied = clipboard.get_image().unwrap();
+        assert_eq!(copied.dimensions(), (25, 15));
+        // Croped pixels start at the crop, the ellipse clears the corners and keeps the center
+        let source = layer.image.as_ref().unwrap().to_rgba8();
+        assert_eq!(copied.get_pixel(12, 7), source.get_pixel(17, 9));
+        assert_eq!(copied.get_pixel(0, 0)[3], 0);
+        assert_eq!(copied.get_pixel(24, 14)[3], 0);
+    }
+
+    #[test]
+    fn a_pasted_image_becomes_the_active_layer() {
+        let ctx = egui::Context::default();
+        let pasted = gradient_image(30, 10).to_rgba8();
+        let mut app = App { clipboard: memory_clipboard(), ..Default::default() };
+        app.clipboard.set_image(&pasted).unwrap();
+        app.paste_image(&ctx);
+
+        assert_eq!(app.output_textures_vec.len(), 1);
+        let layer = &app.output_textures_vec[0];
+        assert_eq!(layer.image.as_ref().unwrap().to_rgba8(), pasted);
+        assert_eq!(layer.transform.size, Vec2::new(30., 10.));
+        assert_eq!(app.active_image.as_ref().map(|active| active.get_texture_id()), Some(layer_id(layer)));
+    }
+
+    #[test]
+    fn a_copied_board_has_the_board_size() {
+        let ctx = egui::Context::default();
+        let mut app = App { clipboard: memory_clipboard(), ..Default::default() };
+        app.main_image_settings.board_size = Vec2::new(320., 240.);
+        app.output_textures_vec.push(test_image_layer(&ctx, "layer", gradient_image(50, 50)));
+        app.copy_board();
+        assert_eq!(app.clipboard.get_image().unwrap().dimensions(), (320, 240));
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+use std::path::Path;
+
+use egui::emath::Rot2;
+use egui::{Pos2, Vec2};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+
+use crate::app_ext::{calc_layer_corners, calc_layer_rects};
+use crate::resource::*;
+
+pub const EXPORT_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "webp"];
+
+#[derive(Debug)]
+pub enum ExportError {
+    EmptyBoard,
+    UnsupportedFormat(String),
+    Image(image::ImageError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::EmptyBoard => write!(f, "the board has no size to export"),
+            ExportError::UnsupportedFormat(ext) => write!(f, "unsupported export format: {ext}"),
+            ExportError::Image(err) => write!(f, "failed to write image: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<image::ImageError> for ExportError {
+    fn from(err: image::ImageError) -> Self {
+        ExportError::Image(err)
+    }
+}
+
+/// Composites every layer onto a board sized canvas, the first layer ends up on top like on screen.
+pub fn flatten_board(layers: &[OutputTexture], board_size: Vec2, background: BoardBackground) -> RgbaImage {
+    let width = board_size.x.round().max(1.) as u32;
+    let height = board_size.y.round().max(1.) as u32;
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba(background.to_rgba()));
+    let board_rect = egui::Rect::from_min_size(Pos2::ZERO, Vec2::new(width as f32, height as f32));
+
+    for layer in layers.iter().rev().filter(|layer| layer.is_visible) {
+        let Some(image) = layer.image.as_ref() else {
+            continue;
+        };
+        let source = image.to_rgba8();
+        if source.width() == 0 || source.height() == 0 {
+            continue;
+        }
+        let source_size = Vec2::new(source.width() as f32, source.height() as f32);
+        let (unclamped_rect, croped_rect) = calc_layer_rects(source_size, &layer.transform, board_rect);
+        let corners = calc_layer_corners(source_size, &layer.transform, board_rect);
+        let visible_rect = egui::Rect::from_points(&corners).intersect(board_rect);
+        if !visible_rect.is_positive() {
+            continue;
+        }
+        // Board pixels are rotated back into the unrotated layer space before sampling
+        let inverse_rotation = Rot2::from_angle(-layer.transform.rotation.to_radians());
+        let center = unclamped_rect.center();
+        let mask_outline = layer.mask.outline(croped_rect.size());
+
+        for y in visible_rect.min.y.floor() as u32..(visible_rect.max.y.ceil() as u32).min(height) {
+            for x in visible_rect.min.x.floor() as u32..(visible_rect.max.x.ceil() as u32).min(width) {
+                let pixel_center = center + inverse_rotation * (Pos2::new(x as f32 + 0.5, y as f32 + 0.5) - center);
+                if !croped_rect.contains(pixel_center) {
+                    continue;
+                }
+                let mask_uv = ((pixel_center - croped_rect.min) / croped_rect.size()).to_pos2();
+                if !layer.mask.contains(mask_uv, &mask_outline) {
+                    continue;
+                }
+                let oriented_uv = ((pixel_center - unclamped_rect.min) / unclamped_rect.size()).to_pos2();
+                let source_uv = layer.transform.oriented_uv(oriented_uv);
+                let color = sample_bilinear(&source, source_uv.x * source_size.x, source_uv.y * source_size.y);
+                blend_over(canvas.get_pixel_mut(x, y), color, layer.transform.opacity);
+            }
+        }
+    }
+    canvas
+}
+
+/// Flattens the board and writes it to `path`, the format is picked from the file extension.
+pub fn save_board(layers: &[OutputTexture], board_size: Vec2, background: BoardBackground, path: &Path) -> Result<(), ExportError> {
+    if board_size.x < 1. || board_size.y < 1. {
+        return Err(ExportError::EmptyBoard);
+    }
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_lowercase();
+    let format = match extension.as_str() {
+        "png" => ImageFormat::Png,
+        "jpg" | "jpeg" => ImageFormat::Jpeg,
+        "webp" => ImageFormat::WebP,
+        _ => return Err(ExportError::UnsupportedFormat(extension)),
+    };
+    if format == ImageFormat::Jpeg {
+        // Jpeg has no alpha channel, a transparent board is flattened onto white
+        let background = match background {
+            BoardBackground::Transparent => BoardBackground::Color(egui::Color32::WHITE),
+            color => color,
+        };
+        let canvas = DynamicImage::ImageRgba8(flatten_board(layers, board_size, background));
+        DynamicImage::ImageRgb8(canvas.to_rgb8()).save_with_format(path, format)?;
+    } else {
+        DynamicImage::ImageRgba8(flatten_board(layers, board_size, background)).save_with_format(path, format)?;
+    }
+    Ok(())
+}
+
+/// The pixels of a layer as seen on the board before rotation, with flips, quarter turns, the crop and the mask applied.
+pub fn layer_image(layer: &OutputTexture) -> Option<RgbaImage> {
+    let image = layer.image.as_ref()?;
+    let transform = &layer.transform;
+    let mut oriented = if transform.flip_horizontal { image.fliph() } else { (**image).clone() };
+    if transform.flip_vertical {
+        oriented = oriented.flipv();
+    }
+    oriented = match transform.quarter_turns % 4 {
+        1 => oriented.rotate90(),
+        2 => oriented.rotate180(),
+        3 => oriented.rotate270(),
+        _ => oriented,
+    };
+    // Crop offsets are board units at scale 1
+    let croped = transform.croped.unwrap_or_default();
+    let to_pixels = |offset: f32| (offset / transform.original_scale).round();
+    let (width, height) = (oriented.width() as f32, oriented.height() as f32);
+    let left = to_pixels(croped.left).clamp(0., width);
+    let top = to_pixels(croped.top).clamp(0., height);
+    let right = (width + to_pixels(croped.right)).clamp(left, width);
+    let bottom = (height + to_pixels(croped.bottom)).clamp(top, height);
+    if right - left < 1. || bottom - top < 1. {
+        return None;
+    }
+    let mut pixels = oriented.crop_imm(left as u32, top as u32, (right - left) as u32, (bottom - top) as u32).to_rgba8();
+    if layer.mask != LayerMask::Rectangle {
+        let size = Vec2::new(pixels.width() as f32, pixels.height() as f32);
+        let mask_outline = layer.mask.outline(size);
+        for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+            let uv = Pos2::new((x as f32 + 0.5) / size.x, (y as f32 + 0.5) / size.y);
+            if !layer.mask.contains(uv, &mask_outline) {
+                pixel[3] = 0;
+            }
+        }
+    }
+    Some(pixels)
+}
+
+fn sample_bilinear(source: &RgbaImage, u: f32, v: f32) -> [f32; 4] {
+    let max_x = source.width() - 1;
+    let max_y = source.height() - 1;
+    let x = (u - 0.5).max(0.);
+    let y = (v - 0.5).max(0.);
+    let x0 = (x.floor() as u32).min(max_x);
+    let y0 = (y.floor() as u32).min(max_y);
+    let x1 = (x0 + 1).min(max_x);
+    let y1 = (y0 + 1).min(max_y);
+    let tx = (x - x0 as f32).clamp(0., 1.);
+    let ty = (y - y0 as f32).clamp(0., 1.);
+
+    let mut color = [0.; 4];
+    for (channel, value) in color.iter_mut().enumerate() {
+        let top = source.get_pixel(x0, y0)[channel] as f32 * (1. - tx) + source.get_pixel(x1, y0)[channel] as f32 * tx;
+        let bottom = source.get_pixel(x0, y1)[channel] as f32 * (1. - tx) + source.get_pixel(x1, y1)[channel] as f32 * tx;
+        *value = top * (1. - ty) + bottom * ty;
+    }
+    color
+}
+
+fn blend_over(dest: &mut Rgba<u8>, color: [f32; 4], opacity: f32) {
+    let src_alpha = color[3] / 255. * opacity.clamp(0., 1.);
+    let dest_alpha = dest[3] as f32 / 255.;
+    let out_alpha = src_alpha + dest_alpha * (1. - src_alpha);
+    if out_alpha <= 0. {
+        *dest = Rgba([0, 0, 0, 0]);
+        return;
+    }
+    for channel in 0..3 {
+        let blended = (color[channel] * src_alpha + dest[channel] as f32 * dest_alpha * (1. - src_alpha)) / out_alpha;
+        dest[channel] = blended.round().clamp(0., 255.) as u8;
+    }
+    dest[3] = (out_alpha * 255.).round() as u8;
+}
@@ -0,0 +1,515 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use egui::TextureId;
+use image::DynamicImage;
+
+use crate::app_ext::update_texture_from_image;
+use crate::layer_tree::normalize;
+use crate::resource::*;
+use crate::tiles::TiledImage;
+
+const MAX_HISTORY: usize = 200;
+
+#[derive(Clone)]
+pub enum BoardCommand {
+    Transform {
+        texture_id: TextureId,
+        before: ImageTranforms,
+        after: ImageTranforms,
+    },
+    /// A group edit of several selected layers, undone as one step.
+    TransformGroup {
+        changes: Vec<(TextureId, ImageTranforms, ImageTranforms)>,
+    },
+    Mask {
+        texture_id: TextureId,
+        before: LayerMask,
+        after: LayerMask,
+    },
+    /// A layer shown, hidden, locked, unlocked or renamed from the layer panel.
+    LayerState {
+        texture_id: TextureId,
+        before: LayerState,
+        after: LayerState,
+    },
+    Arrange {
+        before: LayerArrangement,
+        after: LayerArrangement,
+    },
+    /// A layer put on the board by an import or a paste.
+    Add {
+        index: usize,
+        texture_id: TextureId,
+        texture: Box<OutputTexture>,
+    },
+    Remove {
+        index: usize,
+        texture_id: TextureId,
+        texture: Box<OutputTexture>,
+        /// The groups before the removal, a group emptied by it is dropped and comes back on undo.
+        groups: Vec<LayerGroup>,
+    },
+    ReplacePixels {
+        texture_id: TextureId,
+        before: Box<LayerPixels>,
+        after: Box<LayerPixels>,
+    },
+}
+
+/// Everything a destructive edit replaces on a layer.
+#[derive(Clone)]
+pub struct LayerPixels {
+    pub image: Arc<DynamicImage>,
+    pub tiles: Option<Arc<TiledImage>>,
+    pub file_path: Option<PathBuf>,
+    pub source_bytes: Option<Arc<[u8]>>,
+    pub image_ratio: f32,
+    pub transform: ImageTranforms,
+}
+
+impl LayerPixels {
+    pub fn from_layer(layer: &OutputTexture) -> Option<Self> {
+        Some(Self {
+            image: layer.image.clone()?,
+            tiles: layer.tiles.clone(),
+            file_path: layer.file_path.clone(),
+            source_bytes: layer.source_bytes.clone(),
+            image_ratio: layer.image_ratio,
+            transform: layer.transform,
+        })
+    }
+
+    /// Writes the pixels back to the layer and re-uploads them into the same texture, so the texture id stays valid.
+    pub fn apply_to(&self, layer: &mut OutputTexture) {
+        // The replaced pyramid stays alive in the history, its tiles are not
+        if let Some(replaced) = layer.tiles.as_ref()
+            && !self.tiles.as_ref().is_some_and(|tiles| Arc::ptr_eq(tiles, replaced)) {
+            replaced.release_tiles();
+        }
+        layer.image = Some(self.image.clone());
+        layer.tiles = self.tiles.clone();
+        layer.file_path = self.file_path.clone();
+        layer.source_bytes = self.source_bytes.clone();
+        layer.image_ratio = self.image_ratio;
+        layer.original_scale = self.transform.original_scale;
+        layer.transform = self.transform;
+        if let Some(texture_handle) = layer.texture_handle.as_mut() {
+            let preview = self.tiles.as_ref().map_or(&*self.image, |tiles| tiles.preview());
+            update_texture_from_image(texture_handle, preview);
+        }
+    }
+}
+
+/// The panel toggles and the name of a layer.
+#[derive(Clone, PartialEq)]
+pub struct LayerState {
+    pub name: String,
+    pub is_visible: bool,
+    pub is_locked: bool,
+}
+
+impl LayerState {
+    pub fn from_layer(layer: &OutputTexture) -> Self {
+        Self {
+            name: layer.name.clone(),
+            is_visible: layer.is_visible,
+            is_locked: layer.is_locked,
+        }
+    }
+
+    fn apply_to(&self, layer: &mut OutputTexture) {
+        layer.name = self.name.clone();
+        layer.is_visible = self.is_visible;
+        layer.is_locked = self.is_locked;
+    }
+}
+
+/// Layer order, group membership and the groups themselves, everything reordering and grouping change.
+#[derive(Clone, PartialEq)]
+pub struct LayerArrangement {
+    order: Vec<(TextureId, Option<u64>)>,
+    groups: Vec<LayerGroup>,
+}
+
+impl LayerArrangement {
+    pub fn capture(layers: &[OutputTexture], groups: &[LayerGroup]) -> Self {
+        Self {
+            order: layers.iter().map(|layer| (layer.texture_handle.as_ref().unwrap().id(), layer.group)).collect(),
+            groups: groups.to_vec(),
+        }
+    }
+
+    /// Layers added after the capture are not part of it, they keep their place on top.
+    fn apply_to(&self, layers: &mut Vec<OutputTexture>, groups: &mut Vec<LayerGroup>) {
+        let texture_id = |layer: &OutputTexture| layer.texture_handle.as_ref().unwrap().id();
+        let (mut arranged, unknown): (Vec<_>, Vec<_>) = layers.drain(..).partition(|layer| self.order.iter().any(|(id, _)| *id == texture_id(layer)));
+        arranged.sort_by_key(|layer| self.order.iter().position(|(id, _)| *id == texture_id(layer)));
+        for layer in arranged.iter_mut() {
+            layer.group = self.order.iter().find(|(id, _)| *id == texture_id(layer)).and_then(|(_, group)| *group);
+        }
+        layers.extend(unknown);
+        layers.extend(arranged);
+        *groups = self.groups.clone();
+    }
+}
+
+impl BoardCommand {
+    fn apply(&self, layers: &mut Vec<OutputTexture>, groups: &mut Vec<LayerGroup>, is_undo: bool) {
+        match self {
+            BoardCommand::Transform { texture_id, before, after } => {
+                if let Some(layer) = layers.iter_mut().find(|layer| layer.texture_handle.as_ref().unwrap().id() == *texture_id) {
+                    layer.transform = if is_undo { *before } else { *after };
+                }
+            }
+            BoardCommand::TransformGroup { changes } => {
+                for (texture_id, before, after) in changes {
+                    if let Some(layer) = layers.iter_mut().find(|layer| layer.texture_handle.as_ref().unwrap().id() == *texture_id) {
+                        layer.transform = if is_undo { *before } else { *after };
+                    }
+                }
+            }
+            BoardCommand::Mask { texture_id, before, after } => {
+                if let Some(layer) = layers.iter_mut().find(|layer| layer.texture_handle.as_ref().unwrap().id() == *texture_id) {
+                    layer.mask = if is_undo { before.clone() } else { after.clone() };
+                }
+            }
+            BoardCommand::LayerState { texture_id, before, after } => {
+                if let Some(layer) = layers.iter_mut().find(|layer| layer.texture_handle.as_ref().unwrap().id() == *texture_id) {
+                    if is_undo { before.apply_to(layer) } else { after.apply_to(layer) }
+                }
+            }
+            BoardCommand::Arrange { before, after } => {
+                if is_undo { before.apply_to(layers, groups) } else { after.apply_to(layers, groups) }
+            }
+            // Layers are found by id, the index may point at another layer once more were added
+            BoardCommand::Add { index, texture_id, texture } => {
+                if is_undo { remove_layer(layers, *texture_id) } else { insert_layer(layers, *index, texture) }
+                normalize(layers, groups);
+            }
+            BoardCommand::Remove { index, texture_id, texture, groups: before } => {
+                if is_undo {
+                    insert_layer(layers, *index, texture);
+                    *groups = before.clone();
+                } else {
+                    remove_layer(layers, *texture_id);
+                }
+                normalize(layers, groups);
+            }
+            BoardCommand::ReplacePixels { texture_id, before, after } => {
+                if let Some(layer) = layers.iter_mut().find(|layer| layer.texture_handle.as_ref().unwrap().id() == *texture_id) {
+                    if is_undo { before.apply_to(layer) } else { after.apply_to(layer) }
+                }
+            }
+        }
+    }
+}
+
+fn insert_layer(layers: &mut Vec<OutputTexture>, index: usize, texture: &OutputTexture) {
+    layers.insert(index.min(layers.len()), texture.clone());
+}
+
+fn remove_layer(layers: &mut Vec<OutputTexture>, texture_id: TextureId) {
+    if let Some(idx) = layers.iter().position(|layer| layer.texture_handle.as_ref().unwrap().id() == texture_id) {
+        let removed = layers.remove(idx);
+        if let Some(tiles) = removed.tiles.as_ref() {
+            tiles.release_tiles();
+        }
+    }
+}
+
+/// Undo and redo stacks for board edits.
+/// Transform and mask changes are only recorded once the pointer is released,
+/// so a whole drag or slider scrub ends up as a single entry.
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<BoardCommand>,
+    redo_stack: Vec<BoardCommand>,
+    tracked_transforms: Vec<(TextureId, ImageTranforms)>,
+    tracked_mask: Option<(TextureId, LayerMask)>,
+}
+
+impl History {
+    pub fn push(&mut self, command: BoardCommand) {
+        self.redo_stack.clear();
+        self.undo_stack.push(command);
+        if self.undo_stack.len() > MAX_HISTORY {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn undo(&mut self, layers: &mut Vec<OutputTexture>, groups: &mut Vec<LayerGroup>) -> bool {
+        let Some(command) = self.undo_stack.pop() else {
+            return false;
+        };
+        command.apply(layers, groups, true);
+        self.redo_stack.push(command);
+        true
+    }
+
+    pub fn redo(&mut self, layers: &mut Vec<OutputTexture>, groups: &mut Vec<LayerGroup>) -> bool {
+        let Some(command) = self.redo_stack.pop() else {
+            return false;
+        };
+        command.apply(layers, groups, false);
+        self.undo_stack.push(command);
+        true
+    }
+
+    /// Compares the selected layer transforms with the last recorded ones and records the differences
+    /// when the user is no longer interacting. A changed selection only moves the baseline.
+    pub fn track_transforms(&mut self, selected: Vec<(TextureId, ImageTranforms)>, is_interacting: bool) {
+        let is_same_selection = self.tracked_transforms.len() == selected.len()
+            && self.tracked_transforms.iter().zip(selected.iter()).all(|((tracked_id, _), (texture_id, _))| tracked_id == texture_id);
+        if !is_same_selection {
+            self.tracked_transforms = selected;
+            return;
+        }
+        if is_interacting {
+            return;
+        }
+        let mut changes: Vec<_> = self.tracked_transforms
+            .iter()
+            .zip(selected.iter())
+            .filter(|((_, before), (_, after))| before != after)
+            .map(|((_, before), (texture_id, after))| (*texture_id, *before, *after))
+            .collect();
+        match changes.len() {
+            0 => return,
+            1 => {
+                let (texture_id, before, after) = changes.remove(0);
+                self.push(BoardCommand::Transform { texture_id, before, after });
+            }
+            _ => self.push(BoardCommand::TransformGroup { changes }),
+        }
+        self.tracked_transforms = selected;
+    }
+
+    /// Records a changed mask of the active layer once the user is no longer interacting, like `track_transforms`.
+    pub fn track_mask(&mut self, active: Option<(TextureId, LayerMask)>, is_interacting: bool) {
+        let is_same_layer = self.tracked_mask.as_ref().map(|(id, _)| id) == active.as_ref().map(|(id, _)| id);
+        if !is_same_layer {
+            self.tracked_mask = active;
+            return;
+        }
+        if is_interacting {
+            return;
+        }
+        if let (Some((texture_id, before)), Some((_, after))) = (self.tracked_mask.as_ref(), active.as_ref())
+            && before != after {
+            self.push(BoardCommand::Mask { texture_id: *texture_id, before: before.clone(), after: after.clone() });
+        }
+        self.tracked_mask = active;
+    }
+
+    pub fn reset_tracked_mask(&mut self, active: Option<(TextureId, LayerMask)>) {
+        self.tracked_mask = active;
+    }
+
+    /// Sets the baseline without recording, used after undo/redo or when the layers are replaced.
+    pub fn reset_tracked_transform(&mut self, active: Option<(TextureId, ImageTranforms)>) {
+        self.tracked_transforms = active.into_iter().collect();
+    }
+
+    pub fn reset_tracked_transforms(&mut self, selected: Vec<(TextureId, ImageTranforms)>) {
+        self.tracked_transforms = selected;
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.tracked_transforms.clear();
+        self.tracked_mask = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::Pos2;
+
+    use super::*;
+    use crate::app_ext::{App, AppExt};
+    use crate::test_util::*;
+
+    fn moved(transform: ImageTranforms, x: f32) -> ImageTranforms {
+        ImageTranforms { pos: Pos2::new(x, 0.), ..transform }
+    }
+
+    #[test]
+    fn a_drag_is_recorded_as_one_entry_on_release() {
+        let ctx = egui::Context::default();
+        let mut layers = vec![test_layer(&ctx, "a")];
+        let id = layer_id(&layers[0]);
+        let start = layers[0].transform;
+        let mut history = History::default();
+        history.track_transforms(vec![(id, start)], false);
+        for x in [5., 10., 20.] {
+            layers[0].transform = moved(start, x);
+            history.track_transforms(vec![(id, layers[0].transform)], true);
+        }
+        assert!(!history.can_undo());
+        history.track_transforms(vec![(id, layers[0].transform)], false);
+        assert_eq!(history.undo_stack.len(), 1);
+
+        let mut groups = Vec::new();
+        assert!(history.undo(&mut layers, &mut groups));
+        assert_eq!(layers[0].transform, start);
+        assert!(!history.can_undo());
+        assert!(history.redo(&mut layers, &mut groups));
+        assert_eq!(layers[0].transform.pos, Pos2::new(20., 0.));
+    }
+
+    #[test]
+    fn a_changed_selection_only_moves_the_baseline() {
+        let ctx = egui::Context::default();
+        let layers = [test_layer(&ctx, "a"), test_layer(&ctx, "b")];
+        let mut history = History::default();
+        history.track_transforms(vec![(layer_id(&layers[0]), layers[0].transform)], false);
+        history.track_transforms(vec![(layer_id(&layers[1]), moved(layers[1].transform, 5.))], false);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn a_mask_edit_is_recorded_on_release() {
+        let ctx = egui::Context::default();
+        let mut layers = vec![test_layer(&ctx, "a")];
+        let id = layer_id(&layers[0]);
+        let mut history = History::default();
+        history.track_mask(Some((id, LayerMask::Rectangle)), false);
+        for radius in [0.1, 0.2, 0.3] {
+            history.track_mask(Some((id, LayerMask::RoundedRect(radius))), true);
+        }
+        history.track_mask(Some((id, LayerMask::RoundedRect(0.3))), false);
+        assert_eq!(history.undo_stack.len(), 1);
+
+        let mut groups = Vec::new();
+        layers[0].mask = LayerMask::RoundedRect(0.3);
+        history.undo(&mut layers, &mut groups);
+        assert_eq!(layers[0].mask, LayerMask::Rectangle);
+        history.redo(&mut layers, &mut groups);
+        assert_eq!(layers[0].mask, LayerMask::RoundedRect(0.3));
+    }
+
+    #[test]
+    fn hiding_and_renaming_a_layer_round_trip() {
+        let ctx = egui::Context::default();
+        let mut layers = vec![test_layer(&ctx, "a")];
+        let id = layer_id(&layers[0]);
+        let before = LayerState::from_layer(&layers[0]);
+        layers[0].is_visible = false;
+        layers[0].name = "renamed".to_string();
+        let after = LayerState::from_layer(&layers[0]);
+        let mut groups = Vec::new();
+        let mut history = History::default();
+        history.push(BoardCommand::LayerState { texture_id: id, before, after });
+
+        history.undo(&mut layers, &mut groups);
+        assert!(layers[0].is_visible);
+        assert_eq!(layer_names(&layers), ["a"]);
+        history.redo(&mut layers, &mut groups);
+        assert!(!layers[0].is_visible);
+        assert_eq!(layer_names(&layers), ["renamed"]);
+    }
+
+    #[test]
+    fn a_new_push_clears_redo() {
+        let ctx = egui::Context::default();
+        let mut layers = vec![test_layer(&ctx, "a")];
+        let id = layer_id(&layers[0]);
+        let start = layers[0].transform;
+        let mut groups = Vec::new();
+        let mut history = History::default();
+        history.push(BoardCommand::Transform { texture_id: id, before: start, after: moved(start, 1.) });
+        history.undo(&mut layers, &mut groups);
+        assert!(history.can_redo());
+        history.push(BoardCommand::Transform { texture_id: id, before: start, after: moved(start, 2.) });
+        assert!(!history.can_redo());
+        assert!(!history.redo(&mut layers, &mut groups));
+    }
+
+    #[test]
+    fn undoing_a_removal_puts_the_layer_back_at_its_index() {
+        let ctx = egui::Context::default();
+        let mut layers = vec![test_layer(&ctx, "a"), test_layer(&ctx, "b"), test_layer(&ctx, "c")];
+        let removed = layers.remove(1);
+        let mut groups = Vec::new();
+        let mut history = History::default();
+        history.push(BoardCommand::Remove { index: 1, texture_id: layer_id(&removed), texture: Box::new(removed), groups: Vec::new() });
+
+        history.undo(&mut layers, &mut groups);
+        assert_eq!(layer_names(&layers), ["a", "b", "c"]);
+        history.redo(&mut layers, &mut groups);
+        assert_eq!(layer_names(&layers), ["a", "c"]);
+        history.undo(&mut layers, &mut groups);
+        assert_eq!(layer_names(&layers), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn a_group_edit_round_trips_every_layer() {
+        let ctx = egui::Context::default();
+        let mut layers = vec![test_layer(&ctx, "a"), test_layer(&ctx, "b"), test_layer(&ctx, "c")];
+        let before: Vec<_> = layers.iter().map(|layer| (layer_id(layer), layer.transform)).collect();
+        let mut history = History::default();
+        history.track_transforms(before.clone(), false);
+        layers[0].transform = moved(layers[0].transform, 10.);
+        layers[2].transform.scale = 2.;
+        let after: Vec<_> = layers.iter().map(|layer| (layer_id(layer), layer.transform)).collect();
+        history.track_transforms(after.clone(), false);
+        match history.undo_stack.last() {
+            Some(BoardCommand::TransformGroup { changes }) => assert_eq!(changes.len(), 2),
+            _ => panic!("expected a group entry"),
+        }
+
+        let mut groups = Vec::new();
+        history.undo(&mut layers, &mut groups);
+        assert!(layers.iter().zip(before.iter()).all(|(layer, (_, transform))| layer.transform == *transform));
+        history.redo(&mut layers, &mut groups);
+        assert!(layers.iter().zip(after.iter()).all(|(layer, (_, transform))| layer.transform == *transform));
+    }
+
+    #[test]
+    fn a_redone_removal_takes_out_the_removed_layer_after_a_paste() {
+        let ctx = egui::Context::default();
+        let mut layers = vec![test_layer(&ctx, "a"), test_layer(&ctx, "b"), test_layer(&ctx, "c")];
+        let removed = layers.remove(1);
+        let mut groups = Vec::new();
+        let mut history = History::default();
+        history.push(BoardCommand::Remove { index: 1, texture_id: layer_id(&removed), texture: Box::new(removed), groups: Vec::new() });
+        history.undo(&mut layers, &mut groups);
+
+        // A layer added without an entry shifts every index
+        layers.insert(0, test_layer(&ctx, "pasted"));
+        history.redo(&mut layers, &mut groups);
+        assert_eq!(layer_names(&layers), ["pasted", "a", "c"]);
+    }
+
+    #[test]
+    fn a_paste_is_undone_on_its_own_and_clears_redo() {
+        let ctx = egui::Context::default();
+        let mut app = App::default();
+        for name in ["c", "b", "a"] {
+            app.push_layer(test_layer(&ctx, name));
+        }
+        let removed = layer_id(&app.output_textures_vec[1]);
+        app.remove_image(removed);
+        app.undo();
+        assert_eq!(layer_names(&app.output_textures_vec), ["a", "b", "c"]);
+
+        app.push_layer(test_layer(&ctx, "pasted"));
+        assert!(!app.history.can_redo());
+        app.redo();
+        assert_eq!(layer_names(&app.output_textures_vec), ["pasted", "a", "b", "c"]);
+        app.undo();
+        assert_eq!(layer_names(&app.output_textures_vec), ["a", "b", "c"]);
+        app.redo();
+        assert_eq!(layer_names(&app.output_textures_vec), ["pasted", "a", "b", "c"]);
+    }
+}
@@ -0,0 +1,295 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Cursor, Seek};
+use std::path::{Path, PathBuf};
+
+use egui::Vec2;
+use exif::{In, Reader, Tag, Value};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader};
+
+use crate::resource::*;
+
+/// Images with a longer side are refused before any pixel is decoded.
+pub const MAX_IMAGE_SIDE: u32 = 32768;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    UnsupportedFormat(String),
+    Corrupt(ImageError),
+    TooLarge { width: u32, height: u32, max_side: u32 },
+    OutOfMemory,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "unable to read the file: {err}"),
+            ImportError::UnsupportedFormat(format) => write!(f, "unsupported image format: {format}"),
+            ImportError::Corrupt(err) => write!(f, "the image data is damaged: {err}"),
+            ImportError::TooLarge { width, height, max_side } => write!(f, "the image is too large ({width} x {height}, at most {max_side} pixels per side)"),
+            ImportError::OutOfMemory => write!(f, "the image needs more memory than allowed to decode"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        ImportError::Io(err)
+    }
+}
+
+impl From<ImageError> for ImportError {
+    fn from(err: ImageError) -> Self {
+        match err {
+            ImageError::IoError(err) => ImportError::Io(err),
+            ImageError::Unsupported(err) => ImportError::UnsupportedFormat(err.to_string()),
+            ImageError::Limits(_) => ImportError::OutOfMemory,
+            err => ImportError::Corrupt(err),
+        }
+    }
+}
+
+pub struct DecodedImage {
+    pub image: DynamicImage,
+    pub orientation: Orientation,
+    pub metadata: ImageMetadata,
+}
+
+pub fn decode_image_file(path: &Path) -> Result<DecodedImage, ImportError> {
+    decode_image(ImageReader::open(path)?.with_guessed_format()?)
+}
+
+pub fn decode_image_bytes(bytes: &[u8]) -> Result<DecodedImage, ImportError> {
+    decode_image(ImageReader::new(Cursor::new(bytes)).with_guessed_format()?)
+}
+
+/// Formats the built `image` crate can decode, which ones depends on the enabled codec features.
+pub fn import_formats() -> impl Iterator<Item = ImageFormat> {
+    ImageFormat::all().filter(|format| format.reading_enabled())
+}
+
+pub fn import_extensions() -> Vec<&'static str> {
+    import_formats().flat_map(|format| format.extensions_str().iter().copied()).collect()
+}
+
+pub fn is_importable(path: &Path) -> bool {
+    ImageFormat::from_path(path).is_ok_and(|format| format.reading_enabled())
+}
+
+/// Lists the importable images of a folder sorted by path, subfolders are only walked when `recursive` is set.
+/// Links to folders are not followed so a link back up the tree can not loop,
+/// and a subfolder that can not be read is skipped instead of failing the whole import.
+pub fn collect_image_files(dir: &Path, recursive: bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = Vec::new();
+    list_folder(fs::read_dir(dir)?, recursive, &mut files, &mut pending);
+    while let Some(dir) = pending.pop() {
+        match fs::read_dir(&dir) {
+            Ok(entries) => list_folder(entries, recursive, &mut files, &mut pending),
+            Err(err) => log::warn!("Skipping {}: {err}", dir.display()),
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn list_folder(entries: fs::ReadDir, recursive: bool, files: &mut Vec<PathBuf>, pending: &mut Vec<PathBuf>) {
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // The entry type does not follow links, unlike `Path::is_dir`
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            if recursive {
+                pending.push(path);
+            }
+        } else if is_importable(&path) {
+            files.push(path);
+        }
+    }
+}
+
+/// Splits the board into a grid with one cell per imported layer, as offsets from the board center.
+/// The column count follows the board ratio so the cells stay close to square.
+pub fn layout_cells(count: usize, board_size: Vec2) -> Vec<egui::Rect> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let board_ratio = if board_size.y > 0. { board_size.x / board_size.y } else { 1. };
+    let columns = ((count as f32 * board_ratio).sqrt().round() as usize).clamp(1, count);
+    let rows = count.div_ceil(columns);
+    let cell_size = Vec2::new(board_size.x / columns as f32, board_size.y / rows as f32);
+    let origin = -board_size / 2.;
+    (0..count)
+        .map(|idx| {
+            let cell = Vec2::new((idx % columns) as f32, (idx / columns) as f32);
+            egui::Rect::from_min_size((origin + cell * cell_size).to_pos2(), cell_size)
+        })
+        .collect()
+}
+
+/// Decodes the pixels together with the orientation and the metadata the info panel shows.
+fn decode_image<R: BufRead + Seek>(reader: ImageReader<R>) -> Result<DecodedImage, ImportError> {
+    let format = reader.format();
+    let mut decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    if width.max(height) > MAX_IMAGE_SIDE {
+        return Err(ImportError::TooLarge { width, height, max_side: MAX_IMAGE_SIDE });
+    }
+    let orientation = decoder.orientation()?;
+    let exif_chunk = decoder.exif_metadata()?;
+    let icc_profile = decoder.icc_profile()?;
+    let image = DynamicImage::from_decoder(decoder)?;
+
+    let mut metadata = ImageMetadata {
+        format: format.and_then(|format| format.extensions_str().first()).map(|ext| ext.to_uppercase()),
+        width: image.width(),
+        height: image.height(),
+        color_profile: icc_profile.as_deref().and_then(icc_profile_description),
+        exif_orientation: Some(orientation.to_exif()).filter(|orientation| *orientation != 1),
+        ..Default::default()
+    };
+    if let Some(exif) = exif_chunk.and_then(|chunk| Reader::new().read_raw(chunk).ok()) {
+        let make = ascii_field(&exif, Tag::Make);
+        let model = ascii_field(&exif, Tag::Model);
+        metadata.camera = match (make, model) {
+            // Most models already start with the make
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{make} {model}")),
+            (make, model) => make.or(model),
+        };
+        metadata.date_taken = ascii_field(&exif, Tag::DateTimeOriginal).or_else(|| ascii_field(&exif, Tag::DateTime));
+        metadata.dpi = exif_dpi(&exif);
+        if metadata.color_profile.is_none() {
+            metadata.color_profile = exif.get_field(Tag::ColorSpace, In::PRIMARY).and_then(|field| match field.value.get_uint(0) {
+                Some(1) => Some("sRGB".to_string()),
+                Some(0xFFFF) => Some("Uncalibrated".to_string()),
+                _ => None,
+            });
+        }
+    }
+    Ok(DecodedImage { image, orientation, metadata })
+}
+
+/// Sets the non destructive flips and turns matching an EXIF orientation.
+pub fn apply_exif_orientation(transform: &mut ImageTranforms, orientation: Orientation) {
+    // The image crate turns first and flips second, the transform flips the source first
+    let (flip_horizontal, flip_vertical, quarter_turns) = match orientation {
+        Orientation::NoTransforms => (false, false, 0),
+        Orientation::Rotate90 => (false, false, 1),
+        Orientation::Rotate180 => (false, false, 2),
+        Orientation::Rotate270 => (false, false, 3),
+        Orientation::FlipHorizontal => (true, false, 0),
+        Orientation::FlipVertical => (false, true, 0),
+        Orientation::Rotate90FlipH => (true, false, 3),
+        Orientation::Rotate270FlipH => (true, false, 1),
+    };
+    transform.flip_horizontal = flip_horizontal;
+    transform.flip_vertical = flip_vertical;
+    transform.quarter_turns = quarter_turns;
+}
+
+fn ascii_field(exif: &exif::Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_string())
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
+
+fn exif_dpi(exif: &exif::Exif) -> Option<(f32, f32)> {
+    let resolution = |tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values.first().map(|value| value.to_f64() as f32),
+        _ => None,
+    };
+    let x_resolution = resolution(Tag::XResolution)?;
+    let y_resolution = resolution(Tag::YResolution).unwrap_or(x_resolution);
+    // Resolution unit 3 is centimeters, anything else is treated as inches
+    let unit = exif.get_field(Tag::ResolutionUnit, In::PRIMARY).and_then(|field| field.value.get_uint(0));
+    let to_inch = if unit == Some(3) { 2.54 } else { 1. };
+    Some((x_resolution * to_inch, y_resolution * to_inch))
+}
+
+/// Reads the `desc` tag of an ICC profile, both the v2 `desc` and the v4 `mluc` layouts.
+fn icc_profile_description(icc: &[u8]) -> Option<String> {
+    let read_u32 = |offset: usize| icc.get(offset..offset + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize);
+    let tag_count = read_u32(128)?;
+    let (offset, size) = (0..tag_count).find_map(|idx| {
+        let entry = 132 + idx * 12;
+        (icc.get(entry..entry + 4)? == b"desc").then(|| Some((read_u32(entry + 4)?, read_u32(entry + 8)?)))?
+    })?;
+    let data = icc.get(offset..offset.checked_add(size)?)?;
+    let description = match data.get(0..4)? {
+        b"desc" => {
+            let length = read_u32(offset + 8)?;
+            String::from_utf8_lossy(data.get(12..12 + length)?).trim_end_matches('\0').to_string()
+        }
+        b"mluc" => {
+            let length = read_u32(offset + 20)?;
+            let string_offset = read_u32(offset + 24)?;
+            let utf16: Vec<u16> = data
+                .get(string_offset..string_offset + length)?
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&utf16).trim_end_matches('\0').to_string()
+        }
+        _ => return None,
+    };
+    Some(description.trim().to_string()).filter(|description| !description.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn collects_nested_images_sorted_and_skips_other_files() {
+        let dir = scratch_dir("collect");
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for file in ["b.png", "a.jpg", "notes.txt", "nested/c.png"] {
+            fs::write(dir.join(file), []).unwrap();
+        }
+        assert_eq!(collect_image_files(&dir, false).unwrap(), [dir.join("a.jpg"), dir.join("b.png")]);
+        assert_eq!(collect_image_files(&dir, true).unwrap(), [dir.join("a.jpg"), dir.join("b.png"), dir.join("nested/c.png")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_link_back_up_the_tree_is_not_followed() {
+        let dir = scratch_dir("cycle");
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::write(dir.join("a/image.png"), []).unwrap();
+        std::os::unix::fs::symlink("..", dir.join("a/link")).unwrap();
+        assert_eq!(collect_image_files(&dir, true).unwrap(), [dir.join("a/image.png")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn every_exif_orientation_shows_the_upright_image() {
+        let image = gradient_image(3, 2);
+        let orientations = [
+            Orientation::NoTransforms,
+            Orientation::Rotate90,
+            Orientation::Rotate180,
+            Orientation::Rotate270,
+            Orientation::FlipHorizontal,
+            Orientation::FlipVertical,
+            Orientation::Rotate90FlipH,
+            Orientation::Rotate270FlipH,
+        ];
+        for orientation in orientations {
+            let mut transform = ImageTranforms::default();
+            apply_exif_orientation(&mut transform, orientation);
+            let mut expected = image.clone();
+            expected.apply_orientation(orientation);
+            assert_eq!(orient_by_transform(&image, &transform), expected.to_rgba8(), "{orientation:?}");
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use egui::{ColorImage, Pos2};
+use image::metadata::Orientation;
+use image::DynamicImage;
+
+use crate::import::*;
+use crate::resource::ImageMetadata;
+use crate::tiles::prepare_layer_pixels;
+
+const MAX_WORKERS: usize = 4;
+
+/// Where a layer lands once its image is decoded, offsets are relative to the board center.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ImportPlacement {
+    Center,
+    At(Pos2),
+    Cell(egui::Rect),
+    /// A layer of a project being opened, it keeps its saved transform.
+    Restored(usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImportStatus {
+    Queued,
+    Decoding,
+}
+
+/// An import waiting for its worker, shown as a placeholder layer until it finishes.
+#[derive(Clone, Debug)]
+pub struct PendingImport {
+    pub id: u64,
+    pub path: PathBuf,
+    pub placement: ImportPlacement,
+    pub status: ImportStatus,
+}
+
+/// A decoded image with the pixels already converted for the texture upload.
+/// `levels` holds the tile pyramid of images too large for a single texture.
+pub struct DecodedLayer {
+    pub image: Arc<DynamicImage>,
+    pub orientation: Orientation,
+    pub metadata: ImageMetadata,
+    pub color_image: ColorImage,
+    pub levels: Option<Vec<Arc<DynamicImage>>>,
+}
+
+struct ImportJob {
+    id: u64,
+    path: PathBuf,
+    /// Encoded image read from a bundle, decoded instead of the file at `path`.
+    bytes: Option<Arc<[u8]>>,
+    max_texture_side: u32,
+}
+
+enum ImportEvent {
+    Started(u64),
+    Finished(u64, Box<Result<DecodedLayer, ImportError>>),
+}
+
+#[derive(Default)]
+struct SharedQueue {
+    jobs: Mutex<VecDeque<ImportJob>>,
+    available: Condvar,
+    shutdown: AtomicBool,
+}
+
+/// Decodes images on worker threads, the UI thread only polls the results and uploads the textures.
+/// Workers are started with the first job and stop when the queue is dropped.
+#[derive(Default)]
+pub struct ImportQueue {
+    shared: Arc<SharedQueue>,
+    channel: Option<(Sender<ImportEvent>, Receiver<ImportEvent>)>,
+    pending: Vec<PendingImport>,
+    next_id: u64,
+}
+
+impl ImportQueue {
+    pub fn enqueue(&mut self, ctx: &egui::Context, path: PathBuf, placement: ImportPlacement, max_texture_side: u32) -> u64 {
+        self.push_job(ctx, path, None, placement, max_texture_side)
+    }
+
+    /// Queues an image embedded in a bundle, `path` is its entry name and only shown to the user.
+    pub fn enqueue_bytes(&mut self, ctx: &egui::Context, path: PathBuf, bytes: Arc<[u8]>, placement: ImportPlacement, max_texture_side: u32) -> u64 {
+        self.push_job(ctx, path, Some(bytes), placement, max_texture_side)
+    }
+
+    fn push_job(&mut self, ctx: &egui::Context, path: PathBuf, bytes: Option<Arc<[u8]>>, placement: ImportPlacement, max_texture_side: u32) -> u64 {
+        self.ensure_workers(ctx);
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.push(PendingImport { id, path: path.clone(), placement, status: ImportStatus::Queued });
+        self.shared.jobs.lock().unwrap().push_back(ImportJob { id, path, bytes, max_texture_side });
+        self.shared.available.notify_one();
+        id
+    }
+
+    fn ensure_workers(&mut self, ctx: &egui::Context) {
+        if self.channel.is_some() {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let workers = thread::available_parallelism().map_or(1, |count| count.get()).clamp(1, MAX_WORKERS);
+        for idx in 0..workers {
+            let shared = self.shared.clone();
+            let sender = sender.clone();
+            let ctx = ctx.clone();
+            thread::Builder::new()
+                .name(format!("import-worker-{idx}"))
+                .spawn(move || run_worker(&shared, &sender, &ctx))
+                .expect("failed to spawn an import worker");
+        }
+        self.channel = Some((sender, receiver));
+    }
+
+    pub fn pending(&self) -> &[PendingImport] {
+        &self.pending
+    }
+
+    pub fn is_busy(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Drops a queued job, a job already decoding finishes in the background and its result is thrown away.
+    pub fn cancel(&mut self, id: u64) {
+        self.pending.retain(|pending| pending.id != id);
+        self.shared.jobs.lock().unwrap().retain(|job| job.id != id);
+    }
+
+    pub fn cancel_all(&mut self) {
+        self.pending.clear();
+        self.shared.jobs.lock().unwrap().clear();
+    }
+
+    /// Collects the finished imports in the order they completed.
+    pub fn poll(&mut self) -> Vec<(PendingImport, Result<DecodedLayer, ImportError>)> {
+        let Some((_, receiver)) = self.channel.as_ref() else {
+            return Vec::new();
+        };
+        let mut finished = Vec::new();
+        for event in receiver.try_iter() {
+            match event {
+                ImportEvent::Started(id) => {
+                    if let Some(pending) = self.pending.iter_mut().find(|pending| pending.id == id) {
+                        pending.status = ImportStatus::Decoding;
+                    }
+                }
+                ImportEvent::Finished(id, result) => {
+                    // Cancelled jobs are no longer pending
+                    if let Some(idx) = self.pending.iter().position(|pending| pending.id == id) {
+                        finished.push((self.pending.remove(idx), *result));
+                    }
+                }
+            }
+        }
+        finished
+    }
+}
+
+impl Drop for ImportQueue {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+        self.shared.available.notify_all();
+    }
+}
+
+fn run_worker(shared: &SharedQueue, sender: &Sender<ImportEvent>, ctx: &egui::Context) {
+    loop {
+        let job = {
+            let mut jobs = shared.jobs.lock().unwrap();
+            loop {
+                if shared.shutdown.load(Ordering::Relaxed) {
+                    return;
+                }
+                if let Some(job) = jobs.pop_front() {
+                    break job;
+                }
+                jobs = shared.available.wait(jobs).unwrap();
+            }
+        };
+        if sender.send(ImportEvent::Started(job.id)).is_err() {
+            return;
+        }
+        ctx.request_repaint();
+        let result = Box::new(decode_layer(&job));
+        if sender.send(ImportEvent::Finished(job.id, result)).is_err() {
+            return;
+        }
+        ctx.request_repaint();
+    }
+}
+
+fn decode_layer(job: &ImportJob) -> Result<DecodedLayer, ImportError> {
+    let DecodedImage { image, orientation, metadata } = match job.bytes.as_ref() {
+        Some(bytes) => decode_image_bytes(bytes)?,
+        None => decode_image_file(&job.path)?,
+    };
+    let image = Arc::new(image);
+    let (color_image, levels) = prepare_layer_pixels(&image, job.max_texture_side);
+    Ok(DecodedLayer { image, orientation, metadata, color_image, levels })
+}
@@ -0,0 +1,312 @@
+use std::collections::HashSet;
+
+use egui::TextureId;
+
+use crate::resource::{LayerGroup, OutputTexture};
+
+/// Deepest nesting that is followed, it also stops a damaged project with a group cycle from looping.
+const MAX_GROUP_DEPTH: usize = 32;
+
+/// Something that can be dragged around in the Images panel.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TreeItem {
+    Layer(TextureId),
+    Group(u64),
+}
+
+/// Where a dragged item lands.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DropTarget {
+    /// Right above the item, inside the same group.
+    Above(TreeItem),
+    /// At the top of the group.
+    IntoGroup(u64),
+    /// Below everything, outside of all groups.
+    Bottom,
+}
+
+/// A row of the Images panel, `idx` points into the layer list.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TreeRow {
+    Group { id: u64, depth: usize },
+    Layer { idx: usize, depth: usize },
+}
+
+pub fn find_group(groups: &[LayerGroup], id: u64) -> Option<&LayerGroup> {
+    groups.iter().find(|group| group.id == id)
+}
+
+/// The groups around an item, from the outermost one down to `group` itself.
+pub fn group_path(groups: &[LayerGroup], group: Option<u64>) -> Vec<u64> {
+    let mut path = Vec::new();
+    let mut current = group;
+    while let Some(id) = current
+        && path.len() < MAX_GROUP_DEPTH {
+        let Some(found) = find_group(groups, id) else {
+            break;
+        };
+        path.push(id);
+        current = found.parent;
+    }
+    path.reverse();
+    path
+}
+
+pub fn outermost_group(groups: &[LayerGroup], group: Option<u64>) -> Option<u64> {
+    group_path(groups, group).first().copied()
+}
+
+pub fn next_group_id(groups: &[LayerGroup]) -> u64 {
+    groups.iter().map(|group| group.id).max().map_or(1, |id| id + 1)
+}
+
+pub fn is_layer_visible(groups: &[LayerGroup], layer: &OutputTexture) -> bool {
+    layer.is_visible && layer.group.is_none_or(|group_id| is_group_visible(groups, group_id))
+}
+
+/// Whether the group and every group around it are shown.
+pub fn is_group_visible(groups: &[LayerGroup], group_id: u64) -> bool {
+    group_path(groups, Some(group_id)).iter().all(|id| find_group(groups, *id).is_some_and(|group| group.is_visible))
+}
+
+pub fn is_layer_locked(groups: &[LayerGroup], layer: &OutputTexture) -> bool {
+    layer.is_locked || group_path(groups, layer.group).iter().any(|id| find_group(groups, *id).is_some_and(|group| group.is_locked))
+}
+
+pub fn group_opacity(groups: &[LayerGroup], group: Option<u64>) -> f32 {
+    group_path(groups, group).iter().filter_map(|id| find_group(groups, *id)).map(|group| group.opacity).product()
+}
+
+/// The layers as they are drawn and exported, with the visibility, lock and opacity of their groups folded in.
+pub fn resolved_layers(layers: &[OutputTexture], groups: &[LayerGroup]) -> Vec<OutputTexture> {
+    layers
+        .iter()
+        .map(|layer| {
+            let mut resolved = layer.clone();
+            resolved.is_visible = is_layer_visible(groups, layer);
+            resolved.is_locked = is_layer_locked(groups, layer);
+            resolved.transform.opacity *= group_opacity(groups, layer.group);
+            resolved
+        })
+        .collect()
+}
+
+/// Indices of the layers inside the group, nested groups included.
+pub fn group_members(layers: &[OutputTexture], groups: &[LayerGroup], group_id: u64) -> Vec<usize> {
+    layers
+        .iter()
+        .enumerate()
+        .filter(|(_, layer)| group_path(groups, layer.group).contains(&group_id))
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Rows of the Images panel in draw order, the contents of collapsed groups are left out.
+pub fn tree_rows(layers: &[OutputTexture], groups: &[LayerGroup]) -> Vec<TreeRow> {
+    let is_collapsed = |id: &u64| find_group(groups, *id).is_some_and(|group| group.is_collapsed);
+    let mut rows = Vec::new();
+    let mut open_path: Vec<u64> = Vec::new();
+    for (idx, layer) in layers.iter().enumerate() {
+        let path = group_path(groups, layer.group);
+        let shared = open_path.iter().zip(path.iter()).take_while(|(open, id)| open == id).count();
+        for (depth, id) in path.iter().enumerate().skip(shared) {
+            if !path[..depth].iter().any(is_collapsed) {
+                rows.push(TreeRow::Group { id: *id, depth });
+            }
+        }
+        if !path.iter().any(is_collapsed) {
+            rows.push(TreeRow::Layer { idx, depth: path.len() });
+        }
+        open_path = path;
+    }
+    rows
+}
+
+/// Repairs the tree after loading or rearranging: references to missing groups and group cycles are cut,
+/// groups without layers are dropped and the layers of every group are moved next to each other.
+pub fn normalize(layers: &mut Vec<OutputTexture>, groups: &mut Vec<LayerGroup>) {
+    let ids: HashSet<u64> = groups.iter().map(|group| group.id).collect();
+    let cyclic: HashSet<u64> = groups.iter().filter(|group| group_path(groups, Some(group.id)).len() >= MAX_GROUP_DEPTH).map(|group| group.id).collect();
+    for group in groups.iter_mut() {
+        if group.parent.is_some_and(|parent| !ids.contains(&parent) || cyclic.contains(&group.id)) {
+            group.parent = None;
+        }
+    }
+    for layer in layers.iter_mut() {
+        if layer.group.is_some_and(|group| !ids.contains(&group)) {
+            layer.group = None;
+        }
+    }
+    let used: HashSet<u64> = layers.iter().flat_map(|layer| group_path(groups, layer.group)).collect();
+    groups.retain(|group| used.contains(&group.id));
+
+    let paths: Vec<Vec<u64>> = layers.iter().map(|layer| group_path(groups, layer.group)).collect();
+    let mut order = Vec::with_capacity(layers.len());
+    collect_grouped_order(&paths, (0..layers.len()).collect(), 0, &mut order);
+    let mut taken: Vec<Option<OutputTexture>> = layers.drain(..).map(Some).collect();
+    layers.extend(order.into_iter().filter_map(|idx| taken[idx].take()));
+}
+
+/// Orders the candidates, all sharing the path up to `depth`, by the first appearance of each layer or subgroup.
+fn collect_grouped_order(paths: &[Vec<u64>], candidates: Vec<usize>, depth: usize, order: &mut Vec<usize>) {
+    let mut seen_groups = Vec::new();
+    for idx in candidates.iter().copied() {
+        match paths[idx].get(depth) {
+            None => order.push(idx),
+            Some(group) if !seen_groups.contains(group) => {
+                seen_groups.push(*group);
+                let members = candidates.iter().copied().filter(|member| paths[*member].get(depth) == Some(group)).collect();
+                collect_grouped_order(paths, members, depth + 1, order);
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Moves a layer or a whole group to the target, the moved item joins the group it lands in.
+/// Returns false when nothing moved, like when a group is dropped into itself.
+pub fn move_item(layers: &mut Vec<OutputTexture>, groups: &mut Vec<LayerGroup>, item: TreeItem, target: DropTarget) -> bool {
+    let texture_id = |layer: &OutputTexture| layer.texture_handle.as_ref().unwrap().id();
+    let moving: Vec<usize> = match item {
+        TreeItem::Layer(id) => layers.iter().position(|layer| texture_id(layer) == id).into_iter().collect(),
+        TreeItem::Group(id) => group_members(layers, groups, id),
+    };
+    if moving.is_empty() || target == DropTarget::Above(item) {
+        return false;
+    }
+    // A group can not land anywhere inside itself
+    let target_group = match target {
+        DropTarget::Above(TreeItem::Layer(id)) => layers.iter().find(|layer| texture_id(layer) == id).and_then(|layer| layer.group),
+        DropTarget::Above(TreeItem::Group(id)) | DropTarget::IntoGroup(id) => Some(id),
+        DropTarget::Bottom => None,
+    };
+    if let TreeItem::Group(id) = item
+        && group_path(groups, target_group).contains(&id) {
+        return false;
+    }
+    let new_parent = match target {
+        DropTarget::Above(TreeItem::Group(id)) => find_group(groups, id).and_then(|group| group.parent),
+        _ => target_group,
+    };
+
+    let (mut moved, mut rest): (Vec<_>, Vec<_>) = layers.iter().cloned().enumerate().partition(|(idx, _)| moving.contains(idx));
+    let insert_at = match target {
+        DropTarget::Above(TreeItem::Layer(id)) => rest.iter().position(|(_, layer)| texture_id(layer) == id),
+        DropTarget::Above(TreeItem::Group(id)) | DropTarget::IntoGroup(id) => rest.iter().position(|(_, layer)| group_path(groups, layer.group).contains(&id)),
+        DropTarget::Bottom => Some(rest.len()),
+    };
+    let Some(insert_at) = insert_at else {
+        return false;
+    };
+    match item {
+        TreeItem::Layer(_) => moved[0].1.group = new_parent,
+        TreeItem::Group(id) => {
+            if let Some(group) = groups.iter_mut().find(|group| group.id == id) {
+                group.parent = new_parent;
+            }
+        }
+    }
+    rest.splice(insert_at..insert_at, moved);
+    *layers = rest.into_iter().map(|(_, layer)| layer).collect();
+    normalize(layers, groups);
+    true
+}
+
+/// Puts the layers into a new group at the place of the topmost one and returns the group id.
+pub fn group_layers(layers: &mut Vec<OutputTexture>, groups: &mut Vec<LayerGroup>, members: &[TextureId]) -> Option<u64> {
+    let is_member = |layer: &OutputTexture| members.contains(&layer.texture_handle.as_ref().unwrap().id());
+    let first = layers.iter().position(is_member)?;
+    let id = next_group_id(groups);
+    groups.push(LayerGroup::new(id, layers[first].group));
+    let (mut moved, mut rest): (Vec<_>, Vec<_>) = layers.drain(..).partition(is_member);
+    for layer in moved.iter_mut() {
+        layer.group = Some(id);
+    }
+    // Everything above the topmost member stays above the group
+    rest.splice(first..first, moved);
+    *layers = rest;
+    normalize(layers, groups);
+    Some(id)
+}
+
+/// Dissolves the group, its layers and subgroups move up into its parent.
+pub fn ungroup(layers: &mut [OutputTexture], groups: &mut Vec<LayerGroup>, group_id: u64) {
+    let Some(parent) = find_group(groups, group_id).map(|group| group.parent) else {
+        return;
+    };
+    for layer in layers.iter_mut().filter(|layer| layer.group == Some(group_id)) {
+        layer.group = parent;
+    }
+    for group in groups.iter_mut().filter(|group| group.parent == Some(group_id)) {
+        group.parent = parent;
+    }
+    groups.retain(|group| group.id != group_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{BoardCommand, History, LayerArrangement};
+    use crate::test_util::*;
+
+    fn grouped_layers(ctx: &egui::Context, layers: &[(&str, Option<u64>)]) -> Vec<OutputTexture> {
+        layers.iter().map(|(name, group)| OutputTexture { group: *group, ..test_layer(ctx, name) }).collect()
+    }
+
+    #[test]
+    fn a_cyclic_parent_manifest_is_cut_loose() {
+        let ctx = egui::Context::default();
+        let mut layers = grouped_layers(&ctx, &[("a", Some(1)), ("b", Some(2))]);
+        let mut groups = vec![LayerGroup::new(1, Some(2)), LayerGroup::new(2, Some(1))];
+        normalize(&mut layers, &mut groups);
+        assert!(groups.iter().all(|group| group.parent.is_none()));
+        assert_eq!(group_path(&groups, Some(1)), [1]);
+        assert_eq!(tree_rows(&layers, &groups).len(), 4);
+    }
+
+    #[test]
+    fn a_group_can_not_be_dropped_into_its_own_child() {
+        let ctx = egui::Context::default();
+        let mut layers = grouped_layers(&ctx, &[("a", Some(2)), ("b", Some(1)), ("c", None)]);
+        let mut groups = vec![LayerGroup::new(1, None), LayerGroup::new(2, Some(1))];
+        let inner_layer = TreeItem::Layer(layer_id(&layers[0]));
+        assert!(!move_item(&mut layers, &mut groups, TreeItem::Group(1), DropTarget::IntoGroup(2)));
+        assert!(!move_item(&mut layers, &mut groups, TreeItem::Group(1), DropTarget::Above(inner_layer)));
+        assert_eq!(layer_names(&layers), ["a", "b", "c"]);
+        assert_eq!(find_group(&groups, 2).unwrap().parent, Some(1));
+        assert_eq!(find_group(&groups, 1).unwrap().parent, None);
+    }
+
+    #[test]
+    fn a_layer_dropped_at_the_bottom_leaves_its_groups() {
+        let ctx = egui::Context::default();
+        let mut layers = grouped_layers(&ctx, &[("a", Some(2)), ("b", Some(1)), ("c", None)]);
+        let mut groups = vec![LayerGroup::new(1, None), LayerGroup::new(2, Some(1))];
+        let moved = TreeItem::Layer(layer_id(&layers[0]));
+        assert!(move_item(&mut layers, &mut groups, moved, DropTarget::Bottom));
+        assert_eq!(layer_names(&layers), ["b", "c", "a"]);
+        assert_eq!(layers[2].group, None);
+        // The emptied subgroup is dropped
+        assert_eq!(groups.iter().map(|group| group.id).collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn an_arrangement_round_trips_through_the_history() {
+        let ctx = egui::Context::default();
+        let mut layers = grouped_layers(&ctx, &[("a", None), ("b", None), ("c", None)]);
+        let mut groups = Vec::new();
+        let before = LayerArrangement::capture(&layers, &groups);
+        let members = [layer_id(&layers[0]), layer_id(&layers[2])];
+        let group_id = group_layers(&mut layers, &mut groups, &members).unwrap();
+        let after = LayerArrangement::capture(&layers, &groups);
+        let mut history = History::default();
+        history.push(BoardCommand::Arrange { before, after });
+
+        history.undo(&mut layers, &mut groups);
+        assert_eq!(layer_names(&layers), ["a", "b", "c"]);
+        assert!(groups.is_empty() && layers.iter().all(|layer| layer.group.is_none()));
+        history.redo(&mut layers, &mut groups);
+        assert_eq!(layer_names(&layers), ["a", "c", "b"]);
+        assert_eq!(layers.iter().map(|layer| layer.group).collect::<Vec<_>>(), [Some(group_id), Some(group_id), None]);
+    }
+}
@@ -3,12 +3,31 @@ use egui::{CentralPanel, Color32, CursorIcon, Pos2, RichText, ScrollArea, Sense,
 pub mod resource;
 use resource::*;
 pub mod app_ext;
+pub mod clipboard;
 use app_ext::*;
+pub mod export;
+pub mod history;
+use history::LayerState;
+pub mod import;
+pub mod import_queue;
+pub mod layer_tree;
+use layer_tree::*;
+pub mod perspective;
+pub mod project;
+#[cfg(test)]
+mod test_util;
+pub mod tiles;
+pub mod toast;
+
+/// Room kept to the right of the board viewport for the layer list and settings.
+const SIDE_PANEL_WIDTH: f32 = 380.;
+
 fn main() -> eframe::Result {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
      let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([1500.0, 900.0])
-        .with_resizable(true),
-        // .with_drag_and_drop(true),
+        .with_resizable(true)
+        .with_drag_and_drop(true),
         ..Default::default()
     };
     eframe::run_native("My Image Viewer App", options, Box::new(|cc| Ok(Box::new(App::new(cc)))))
@@ -18,18 +37,91 @@ fn main() -> eframe::Result {
 impl eframe::App for App {
     
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_history_shortcuts(ctx);
+        self.handle_clipboard_shortcuts(ctx);
+        self.handle_file_drop(ctx);
+        self.receive_imports(ctx);
+        self.receive_perspective_crop();
+        self.evict_unused_tiles(ctx);
         CentralPanel::default().show(ctx, |ui| {
-            if ui.button("Import Image").clicked(){
-                // let file_path = pick_file();
-                self.import_image(ctx);
-            }
             ui.horizontal(|ui| {
-                ui.vertical(|ui| {
-                    let main_image_settings = &mut self.main_image_settings;
-                    let main_image_rect = main_image_settings.image_plot_rect;
-                   
-                    
-                    ui.painter().rect_filled(main_image_rect, 0.0, Color32::from_rgb(200, 200, 200));
+                if ui.button("Import Images").clicked(){
+                    // let file_path = pick_file();
+                    self.import_images(ctx);
+                }
+                ui.menu_button("Import Folder", |ui| {
+                    if ui.button("This folder only").clicked() {
+                        self.import_folder(ctx, false);
+                    }
+                    if ui.button("Including subfolders").clicked() {
+                        self.import_folder(ctx, true);
+                    }
+                });
+                if ui.button("Export Board").on_hover_text("Export the board as PNG, JPEG or WebP").clicked() {
+                    self.export_board();
+                }
+                if ui.button("Copy Board").on_hover_text("Copy the flattened board to the clipboard").clicked() {
+                    self.copy_board();
+                }
+                ui.separator();
+                if ui.button("Open Project").clicked() {
+                    self.open_project(ctx);
+                }
+                if ui.button("Save Project").clicked() {
+                    self.save_project();
+                }
+                if ui.button("Save Bundle").on_hover_text("Save the project with every image embedded").clicked() {
+                    self.save_bundle();
+                }
+                ui.separator();
+                let is_cropping = self.active_image_settings.croped_modified.is_modifying;
+                let undo_button = ui.add_enabled(!is_cropping && self.history.can_undo(), egui::Button::new(RichText::new(egui_phosphor::regular::ARROW_COUNTER_CLOCKWISE.to_string()).size(14.0)));
+                if undo_button.on_hover_text("Undo (Ctrl+Z)").clicked() {
+                    self.undo();
+                }
+                let redo_button = ui.add_enabled(!is_cropping && self.history.can_redo(), egui::Button::new(RichText::new(egui_phosphor::regular::ARROW_CLOCKWISE.to_string()).size(14.0)));
+                if redo_button.on_hover_text("Redo (Ctrl+Shift+Z)").clicked() {
+                    self.redo();
+                }
+                ui.separator();
+                let viewport_rect = self.main_image_settings.viewport_rect;
+                let board_size = self.main_image_settings.board_size;
+                ui.label(format!("{:.0}%", self.main_image_settings.view.zoom * 100.))
+                    .on_hover_text("Scroll to zoom, drag with the middle button or Space to pan");
+                if ui.button("Fit Board").clicked() {
+                    self.main_image_settings.view.fit(viewport_rect, board_size);
+                }
+                if ui.button("100%").clicked() {
+                    self.main_image_settings.view.set_centered(viewport_rect, board_size, 1.);
+                }
+                if self.imports.is_busy() {
+                    ui.separator();
+                    ui.add(egui::Spinner::new());
+                    ui.label(format!("Importing {} image(s)", self.imports.pending().len()));
+                    if ui.button("Cancel all").clicked() {
+                        self.imports.cancel_all();
+                    }
+                }
+            });
+            self.display_error_message(ctx);
+            self.toasts.show(ctx);
+            ui.horizontal(|ui| {
+                let viewport_size = Vec2::new((ui.available_width() - SIDE_PANEL_WIDTH).max(200.), ui.available_height());
+                let (viewport_rect, _) = ui.allocate_exact_size(viewport_size, Sense::hover());
+                // The board can be zoomed past the viewport, its ui is clipped and does not push the side panel
+                let mut board_ui = ui.new_child(egui::UiBuilder::new().max_rect(viewport_rect).layout(egui::Layout::top_down(egui::Align::Min)));
+                board_ui.set_clip_rect(viewport_rect.intersect(ui.clip_rect()));
+                board_ui.scope(|ui| {
+                    self.main_image_settings.update_board_rect(viewport_rect);
+                    self.handle_board_view_input(ctx, ui);
+                    let main_image_rect = self.main_image_settings.image_plot_rect;
+
+                    ui.painter().rect_filled(viewport_rect, 0.0, Color32::from_rgb(60, 60, 60));
+                    paint_board_background(ui.painter(), main_image_rect, self.main_image_settings.background);
+                    // Registered before every other board widget, it only gets the pointer over empty space
+                    if !self.active_image_settings.croped_modified.is_modifying {
+                        self.add_rubber_band_selection(ctx, ui);
+                    }
                     self.add_main_image_rect_setting_control(ctx, ui);
                     
                     if self.active_image_settings.croped_modified.is_modifying {
@@ -43,11 +135,19 @@ impl eframe::App for App {
                         }
                     } else {
                        
-                        for (idx, output_texture) in self.output_textures_vec.clone().iter_mut().rev().enumerate() {
-                            let texture_rect = self.draw_image_to_board(ui,  output_texture);
-                            self.add_drag_events_to_image(ctx, ui, texture_rect, self.output_textures_vec.len() -1 -idx);
+                        // Drawn with the visibility, lock and opacity of their groups
+                        for (idx, output_texture) in resolved_layers(&self.output_textures_vec, &self.layer_groups).iter_mut().rev().enumerate() {
+                            if !output_texture.is_visible {
+                                continue;
+                            }
+                            let texture_quad = self.draw_image_to_board(ui,  output_texture);
+                            self.add_drag_events_to_image(ctx, ui, texture_quad, self.output_textures_vec.len() -1 -idx);
                             
                         }
+                        self.draw_selection_outlines(ui);
+                        self.add_transform_handles(ctx, ui);
+                        self.add_mask_handles(ctx, ui);
+                        self.draw_pending_imports(ui);
                     }
 
 
@@ -64,15 +164,22 @@ impl eframe::App for App {
                 ui.vertical(|ui| {
                 // ui.available_size();
                     ui.add_space(30.0);
+                    egui::CollapsingHeader::new("Board").show(ui, |ui| {
+                        self.display_board_settings(ui);
+                    });
                     ui.vertical(|ui| {
                         ui.horizontal(|ui| {
                             ui.label(RichText::new("Images").size(24.0));
                             if !self.image_sorting_modify.is_sorting {
-                                let ordering_button = ui.button(egui::RichText::new(format!("{}", egui_phosphor::regular::ARROWS_DOWN_UP)).size(12.0).color(Color32::WHITE));
+                                let ordering_button = ui.button(egui::RichText::new(egui_phosphor::regular::ARROWS_DOWN_UP.to_string()).size(12.0).color(Color32::WHITE));
                                 if ordering_button.clicked() {
                                     self.image_sorting_modify.is_sorting = true; 
                                 }
                             };
+                            let group_button = ui.add_enabled(!self.selection.layers.is_empty(), egui::Button::new(egui::RichText::new(egui_phosphor::regular::FOLDER_PLUS.to_string()).size(12.0).color(Color32::WHITE)));
+                            if group_button.on_hover_text("Group the selected layers").clicked() {
+                                self.group_selected_layers();
+                            }
                         });
 
                         let image_preview_scroll_area = ScrollArea::vertical().max_height(300.0);
@@ -83,47 +190,83 @@ impl eframe::App for App {
                             let image_preview_cover_size = Vec2 {x: 250., y: 60.};
                             let margin: f32 = 2.5;
                             let padding: f32 = 5.;
-                            for (idx, image_preview) in self.output_textures_vec.clone().iter().enumerate() {
+                            let indent: f32 = 16.;
+                            self.display_pending_imports(ui);
+                            let layers = self.output_textures_vec.clone();
+                            for row in tree_rows(&layers, &self.layer_groups) {
+                                let (idx, depth) = match row {
+                                    TreeRow::Group { id, depth } => {
+                                        ui.add_space(margin);
+                                        ui.horizontal(|ui| {
+                                            ui.add_space(indent * depth as f32);
+                                            self.display_layer_group_row(ui, id, image_preview_cover_size.x - indent * depth as f32);
+                                        });
+                                        continue;
+                                    }
+                                    TreeRow::Layer { idx, depth } => (idx, depth),
+                                };
+                                let image_preview = &layers[idx];
                                 ui.add_space(margin);
                                 ui.horizontal(|ui| {
-
-                                    let (image_cover_rect, _) = ui.allocate_exact_size(image_preview_cover_size, Sense::click());
+                                    ui.add_space(indent * depth as f32);
+                                    let (image_cover_rect, _) = ui.allocate_exact_size(image_preview_cover_size - Vec2::new(indent * depth as f32, 0.), Sense::click());
                                     
-                                    ui.painter().rect_filled(image_cover_rect, 5., Color32::from_rgb(255, 255, 255));
+                                    let is_selected = self.selection.is_group() && self.selection.contains(image_preview.texture_handle.as_ref().unwrap().id());
+                                    let cover_color = if is_selected { Color32::from_rgb(200, 225, 255) } else { Color32::from_rgb(255, 255, 255) };
+                                    ui.painter().rect_filled(image_cover_rect, 5., cover_color);
                                     let frame_width = image_cover_rect.width() - padding * 2.0;
                                     let frame_height = image_cover_rect.height() - padding * 2.0;
                                     let frame_ratio = frame_width / frame_height;
-                                    let txt_ratio = image_preview.image_ratio;
+                                    let preview_size = image_preview.transform.oriented_size(image_preview.texture_handle.as_ref().unwrap().size_vec2());
+                                    let txt_ratio = preview_size.x / preview_size.y;
                                 
                                     
                                     let scaled = if txt_ratio > frame_ratio {
-                                        frame_width / image_preview.texture_handle.as_ref().unwrap().size_vec2().x as f32
+                                        frame_width / preview_size.x
                                     } else {
-                                        frame_height / image_preview.texture_handle.as_ref().unwrap().size_vec2().y as f32
+                                        frame_height / preview_size.y
                                     };
                                     
                                     let img_preview_rect = egui::Rect::from_center_size(
-                                        Pos2 {x: padding * 5. + image_cover_rect.min.x + image_preview.texture_handle.as_ref().unwrap().size_vec2().x * scaled / 2.0, y: image_cover_rect.center().y},
-                                        Vec2 { x: image_preview.texture_handle.as_ref().unwrap().size_vec2().x * scaled, y: image_preview.texture_handle.as_ref().unwrap().size_vec2().y as f32 * scaled }
+                                        Pos2 {x: padding * 5. + image_cover_rect.min.x + preview_size.x * scaled / 2.0, y: image_cover_rect.center().y},
+                                        preview_size * scaled
                                     );
     
                                     let preview_uv = egui::Rect::from_min_max(
                                         Pos2 { x: 0.0, y: 0.0 }, 
                                         Pos2 { x: 1.0, y: 1.0 }
                                     );
-                                    ui.painter().image(image_preview.texture_handle.as_ref().unwrap().id(), img_preview_rect, preview_uv, Color32::WHITE);
+                                    // Hidden layers keep a faded thumbnail
+                                    let preview_tint = if is_layer_visible(&self.layer_groups, image_preview) { Color32::WHITE } else { Color32::from_white_alpha(70) };
+                                    ui.painter().add(oriented_image_mesh(image_preview.texture_handle.as_ref().unwrap().id(), img_preview_rect, preview_uv, preview_tint, &image_preview.transform));
                                     ui.horizontal_centered(|ui| {
-                                        ui.vertical_centered_justified( |ui| {
-                                            
-                                            
-                                            let remove_button = ui.button(egui::RichText::new(format!("{}", egui_phosphor::regular::TRASH)).size(16.0));
-                                            if remove_button.clicked(){
-                                                self.remove_image(image_preview.texture_handle.as_ref().unwrap().id());
-                                            }
-                                        });
+                                        let (visibility_icon, visibility_text) = if image_preview.is_visible {
+                                            (egui_phosphor::regular::EYE, "Hide layer")
+                                        } else {
+                                            (egui_phosphor::regular::EYE_SLASH, "Show layer")
+                                        };
+                                        if ui.button(RichText::new(visibility_icon).size(16.0)).on_hover_text(visibility_text).clicked() {
+                                            let before = LayerState::from_layer(&self.output_textures_vec[idx]);
+                                            self.output_textures_vec[idx].is_visible = !image_preview.is_visible;
+                                            self.record_layer_state(idx, before);
+                                        }
+                                        let (lock_icon, lock_text) = if image_preview.is_locked {
+                                            (egui_phosphor::regular::LOCK, "Unlock layer")
+                                        } else {
+                                            (egui_phosphor::regular::LOCK_OPEN, "Lock layer")
+                                        };
+                                        if ui.button(RichText::new(lock_icon).size(16.0)).on_hover_text(lock_text).clicked() {
+                                            let before = LayerState::from_layer(&self.output_textures_vec[idx]);
+                                            self.output_textures_vec[idx].is_locked = !image_preview.is_locked;
+                                            self.record_layer_state(idx, before);
+                                        }
+                                        let remove_button = ui.button(egui::RichText::new(egui_phosphor::regular::TRASH.to_string()).size(16.0));
+                                        if remove_button.clicked(){
+                                            self.remove_image(image_preview.texture_handle.as_ref().unwrap().id());
+                                        }
                                     });
-                                    if let Some(active_img) = &self.active_image {
-                                        if active_img.get_texture_id() == image_preview.texture_handle.as_ref().unwrap().id() {
+                                    if let Some(active_img) = &self.active_image
+                                        && active_img.get_texture_id() == image_preview.texture_handle.as_ref().unwrap().id() {
                                             
                                             let points = Vec::from([
                                                 Pos2::new(image_cover_rect.min.x + padding, image_cover_rect.center().y - padding * 2. ),
@@ -133,22 +276,69 @@ impl eframe::App for App {
     
                                             ]);
                                             ui.painter().line(points, Stroke::new(1., Color32::BLACK));
-                                        }
-                                    };
-                                    let click_res = ui.allocate_rect(image_cover_rect, Sense::click());
+                                        };
+                                    let click_res = ui.allocate_rect(image_cover_rect, Sense::click_and_drag());
                                     if click_res.hovered() {
                                         ctx.set_cursor_icon(CursorIcon::PointingHand);
                                     }
+                                    let tree_item = TreeItem::Layer(image_preview.texture_handle.as_ref().unwrap().id());
+                                    if click_res.drag_started() {
+                                        egui::DragAndDrop::set_payload(ctx, tree_item);
+                                    }
+                                    if click_res.dnd_hover_payload::<TreeItem>().is_some() {
+                                        paint_drop_hint(ui, image_cover_rect, false);
+                                    }
+                                    if let Some(item) = click_res.dnd_release_payload::<TreeItem>() {
+                                        self.move_tree_item(*item, DropTarget::Above(tree_item));
+                                    }
                                     if click_res.clicked() {
-                                        self.select_image(idx);
+                                        let modifiers = ctx.input(|i| i.modifiers);
+                                        if modifiers.shift {
+                                            self.select_range(idx);
+                                        } else if modifiers.command {
+                                            self.toggle_selection(idx);
+                                        } else {
+                                            self.select_image(idx);
+                                        }
+                                    }
+                                    // Added after the cover so it gets the clicks, the rest of the cover still selects the layer
+                                    let name_rect = egui::Rect::from_min_max(
+                                        Pos2::new(img_preview_rect.max.x + padding * 2., image_cover_rect.center().y - 11.),
+                                        Pos2::new(image_cover_rect.max.x - padding, image_cover_rect.center().y + 11.),
+                                    );
+                                    if name_rect.is_positive() {
+                                        let mut name = image_preview.name.clone();
+                                        let name_edit = egui::TextEdit::singleline(&mut name).hint_text("Layer name").text_color(Color32::BLACK).frame(false);
+                                        let name_response = ui.put(name_rect, name_edit);
+                                        // The name is kept from when editing started, so a rename is one entry once it is committed
+                                        if name_response.gained_focus() {
+                                            let before = LayerState::from_layer(&self.output_textures_vec[idx]);
+                                            ui.data_mut(|data| data.insert_temp(name_response.id, before));
+                                        }
+                                        if name_response.changed() {
+                                            self.output_textures_vec[idx].name = name;
+                                        }
+                                        if name_response.lost_focus()
+                                            && let Some(before) = ui.data_mut(|data| {
+                                                let before = data.get_temp::<LayerState>(name_response.id);
+                                                data.remove::<LayerState>(name_response.id);
+                                                before
+                                            }) {
+                                            self.record_layer_state(idx, before);
+                                        }
                                     }
                                 });
-                                
-
-                                
-
                             }
-                            
+                            // Dropping below the last row takes the item out of every group
+                            if egui::DragAndDrop::has_any_payload(ctx) {
+                                let (bottom_rect, bottom_res) = ui.allocate_exact_size(Vec2::new(image_preview_cover_size.x, 20.), Sense::hover());
+                                if bottom_res.dnd_hover_payload::<TreeItem>().is_some() {
+                                    paint_drop_hint(ui, bottom_rect, false);
+                                }
+                                if let Some(item) = bottom_res.dnd_release_payload::<TreeItem>() {
+                                    self.move_tree_item(*item, DropTarget::Bottom);
+                                }
+                            }
                         });
                         
                        
@@ -158,16 +348,43 @@ impl eframe::App for App {
                     });
                     ui.separator();
                     egui::CollapsingHeader::new("Active Image Settings").show(ui, |ui| { 
+                        if self.selection.is_group() {
+                            ui.label(format!("{} layers selected, moving, scaling and opacity apply to all of them", self.selection.layers.len()));
+                        }
                         if self.active_image_settings.croped_modified.is_modifying {
                             ui.label("Unable to scale while cropping");
                         } else {
-                            let scale_slider = egui::Slider::new(&mut self.active_image_settings.transforms.scale, 0.1..=10.).text("Scale");
+                            let scale_slider = egui::Slider::new(&mut self.active_image_settings.transforms.scale, MIN_LAYER_SCALE..=MAX_LAYER_SCALE).text("Scale");
                             // scale_slider
                             ui.add(scale_slider);
+                            let is_stretched = self.active_image_settings.transforms.aspect_stretch != 1.;
+                            if ui.add_enabled(is_stretched, egui::Button::new("Reset Aspect")).on_hover_text("Undo the stretch from the edge handles").clicked() {
+                                self.active_image_settings.transforms.aspect_stretch = 1.;
+                            }
 
                         }
                         ui.add(egui::Slider::new(&mut self.active_image_settings.transforms.opacity, 0.0..=1.).text("Opacity"));
-                        // ui.add(egui::Slider::new(&mut self.active_image_settings.transforms.rotation, -180.0..=180.0).text("Rotation"));
+                        ui.add(egui::Slider::new(&mut self.active_image_settings.transforms.rotation, -180.0..=180.0).suffix("°").text("Rotation"));
+                        ui.add_enabled_ui(self.active_image.is_some() && !self.active_image_settings.croped_modified.is_modifying, |ui| {
+                            ui.horizontal(|ui| {
+                                let orientation_buttons = [
+                                    (egui_phosphor::regular::FLIP_HORIZONTAL, "Flip horizontally", OrientationOp::FlipHorizontal),
+                                    (egui_phosphor::regular::FLIP_VERTICAL, "Flip vertically", OrientationOp::FlipVertical),
+                                    (egui_phosphor::regular::ARROW_ARC_LEFT, "Rotate 90° counter clockwise", OrientationOp::RotateCounterClockwise),
+                                    (egui_phosphor::regular::ARROW_ARC_RIGHT, "Rotate 90° clockwise", OrientationOp::RotateClockwise),
+                                    (egui_phosphor::regular::ARROWS_CLOCKWISE, "Rotate 180°", OrientationOp::Rotate180),
+                                ];
+                                for (icon, hover_text, op) in orientation_buttons {
+                                    if ui.button(RichText::new(icon).size(16.0)).on_hover_text(hover_text).clicked() {
+                                        self.apply_orientation(op);
+                                    }
+                                }
+                                let has_orientation = self.active_image_settings.transforms.has_orientation();
+                                if ui.add_enabled(has_orientation, egui::Button::new("Bake")).on_hover_text("Rewrite the image pixels with the flips and turns").clicked() {
+                                    self.bake_orientation();
+                                }
+                            });
+                        });
                         egui::CollapsingHeader::new("Position").show(ui, |ui| {
                             ui.horizontal(|ui| {
                                 ui.add(egui::DragValue::new(&mut self.active_image_settings.transforms.pos.x).speed(1.).prefix("X: "));
@@ -176,17 +393,23 @@ impl eframe::App for App {
                             });
                         })
                     });
+                    if self.active_image.is_some() {
+                        egui::CollapsingHeader::new("Image Info").show(ui, |ui| {
+                            self.display_image_info(ui);
+                        });
+                    }
                    
                     if self.active_image.is_some() {
-                        if self.active_image_settings.croped_modified.is_modifying == false {
+                        if !self.active_image_settings.croped_modified.is_modifying {
                             let crop_button = ui.button("Crop Image").on_hover_text("Crop the current image");
                             if crop_button.clicked() {
                                 
                                 
-                                let max_scale = calc_max_scale(self.active_image_settings.transforms.size, self.main_image_settings.image_plot_rect.size()) / self.active_image_settings.transforms.original_scale.clone();
+                                let oriented_size = self.active_image_settings.transforms.oriented_size(self.active_image_settings.transforms.size);
+                                let max_scale = calc_max_scale(oriented_size, self.main_image_settings.board_size) / self.active_image_settings.transforms.original_scale;
                                 let crop = if let Some(crop) = self.active_image_settings.transforms.croped {
 
-                                    let multiply =  max_scale.clone();
+                                    let multiply =  max_scale;
                                     Some(CropRect {
                                         top: crop.top * multiply,
                                         left: crop.left * multiply,
@@ -196,12 +419,21 @@ impl eframe::App for App {
                                 } else {
                                     Some(CropRect::default())
                                 };
-                                self.active_image_settings.croped_modified = CropedImageModified::default();
+                                // The mode sticks between crops, the corners start from the whole image again
+                                let mode = self.active_image_settings.croped_modified.mode;
+                                self.active_image_settings.croped_modified = CropedImageModified { mode, ..Default::default() };
                                 self.active_image_settings.croped_modified.is_modifying = true;
                                 self.active_image_settings.croped_modified.max_scale = max_scale;
-                                self.active_image_settings.croped_modified.prev_modified = crop.clone();
-                                self.active_image_settings.croped_modified.current_modified = crop.clone();
+                                self.active_image_settings.croped_modified.prev_modified = crop;
+                                self.active_image_settings.croped_modified.current_modified = crop;
                             }
+                            let has_crop = self.active_image_settings.transforms.croped.is_some_and(|crop| crop != CropRect::default());
+                            if ui.add_enabled(has_crop, egui::Button::new("Bake Crop")).on_hover_text("Drop the pixels outside of the crop from the image").clicked() {
+                                self.bake_crop(ctx);
+                            }
+                            egui::CollapsingHeader::new("Mask").show(ui, |ui| {
+                                self.display_mask_settings(ui);
+                            });
                         } else {
                             ui.horizontal(|ui| {
                                 let cancel_button =ui.button("Cancel").on_hover_text("Cancel Croping");
@@ -212,12 +444,17 @@ impl eframe::App for App {
                                 }
                                 if apply_button.clicked() {
                                     self.active_image_settings.croped_modified.is_modifying = false;
-                                    self.update_croped_image_tranform();
+                                    match self.active_image_settings.croped_modified.mode {
+                                        CropMode::Rectangle => self.update_croped_image_tranform(),
+                                        CropMode::Perspective => self.apply_perspective_crop(ctx),
+                                    }
                                 }
                                 if reset_button.clicked() {
                                     self.active_image_settings.croped_modified.current_modified = self.active_image_settings.croped_modified.prev_modified;
+                                    self.active_image_settings.croped_modified.perspective = perspective::FULL_QUAD;
                                 }
                             });
+                            self.display_crop_settings(ui);
                         }
                         egui::CollapsingHeader::new("Uv Display").show(ui, |ui| {
                             let (uv_display_rect, _uv_display_response) = ui.allocate_exact_size(Vec2 { x: 300., y: 300. }, Sense::click());
@@ -246,5 +483,6 @@ impl eframe::App for App {
           
             });
         });
+        self.record_history(ctx);
     }
 }
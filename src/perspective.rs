@@ -0,0 +1,199 @@
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+
+use egui::{Pos2, Vec2};
+use image::imageops::interpolate_bilinear;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::tiles::{build_levels, needs_tiles};
+
+/// Corners of a quad in the order top left, top right, bottom right, bottom left.
+pub type Quad = [Pos2; 4];
+
+/// The corners of the whole image in uv, where a new perspective crop starts.
+pub const FULL_QUAD: Quad = [Pos2::new(0., 0.), Pos2::new(1., 0.), Pos2::new(1., 1.), Pos2::new(0., 1.)];
+
+/// Projective map from the unit square onto a quad, the square corners land on the quad corners in order.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Homography {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+    f: f32,
+    g: f32,
+    h: f32,
+}
+
+impl Homography {
+    pub fn square_to_quad(quad: Quad) -> Self {
+        let [p0, p1, p2, p3] = quad;
+        let sum = p0.to_vec2() - p1.to_vec2() + p2.to_vec2() - p3.to_vec2();
+        let (g, h) = if sum.length_sq() < f32::EPSILON {
+            // A parallelogram needs no perspective division
+            (0., 0.)
+        } else {
+            let (side_1, side_2) = (p1 - p2, p3 - p2);
+            let denominator = side_1.x * side_2.y - side_2.x * side_1.y;
+            if denominator.abs() < f32::EPSILON {
+                (0., 0.)
+            } else {
+                ((sum.x * side_2.y - side_2.x * sum.y) / denominator, (side_1.x * sum.y - sum.x * side_1.y) / denominator)
+            }
+        };
+        Self {
+            a: p1.x - p0.x + g * p1.x,
+            b: p3.x - p0.x + h * p3.x,
+            c: p0.x,
+            d: p1.y - p0.y + g * p1.y,
+            e: p3.y - p0.y + h * p3.y,
+            f: p0.y,
+            g,
+            h,
+        }
+    }
+
+    /// Maps a point of the unit square onto the quad.
+    pub fn map(&self, uv: Pos2) -> Pos2 {
+        let w = self.g * uv.x + self.h * uv.y + 1.;
+        Pos2::new((self.a * uv.x + self.b * uv.y + self.c) / w, (self.d * uv.x + self.e * uv.y + self.f) / w)
+    }
+}
+
+/// Whether the corners still form a convex quad without crossing sides, the only shape that can be straightened.
+pub fn is_convex_quad(quad: Quad) -> bool {
+    let cross = |idx: usize| {
+        let (a, b, c) = (quad[idx], quad[(idx + 1) % 4], quad[(idx + 2) % 4]);
+        (b - a).x * (c - b).y - (b - a).y * (c - b).x
+    };
+    let crosses = [cross(0), cross(1), cross(2), cross(3)];
+    crosses.iter().all(|cross| *cross > 0.) || crosses.iter().all(|cross| *cross < 0.)
+}
+
+/// Pixel size of the straightened image, the longer of each pair of opposite sides so no detail is lost.
+pub fn rectified_size(quad: Quad) -> (u32, u32) {
+    let [p0, p1, p2, p3] = quad;
+    let width = (p1 - p0).length().max((p2 - p3).length());
+    let height = (p3 - p0).length().max((p2 - p1).length());
+    (width.round().max(1.) as u32, height.round().max(1.) as u32)
+}
+
+/// Cuts the quad, given in pixels of `image`, out into an upright rectangle of `size`.
+/// Only the pixels around the quad are converted, a small quad on a large scan stays cheap.
+pub fn rectify(image: &DynamicImage, quad: Quad, size: (u32, u32)) -> DynamicImage {
+    let bounds = egui::Rect::from_points(&quad).expand(1.).intersect(egui::Rect::from_min_size(Pos2::ZERO, Vec2::new(image.width() as f32, image.height() as f32)));
+    let (left, top) = (bounds.min.x.floor().max(0.) as u32, bounds.min.y.floor().max(0.) as u32);
+    let (right, bottom) = ((bounds.max.x.ceil() as u32).max(left + 1), (bounds.max.y.ceil() as u32).max(top + 1));
+    let source = image.crop_imm(left, top, right - left, bottom - top).to_rgba8();
+    let homography = Homography::square_to_quad(quad.map(|corner| corner - Vec2::new(left as f32, top as f32)));
+    let (width, height) = size;
+    let max = Vec2::new(source.width().saturating_sub(1) as f32, source.height().saturating_sub(1) as f32);
+    let rectified = RgbaImage::from_fn(width, height, |x, y| {
+        let uv = Pos2::new((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32);
+        // Pixel centers sit at half pixels, the sampler wants them at whole ones
+        let pixel = (homography.map(uv) - Vec2::splat(0.5)).clamp(Pos2::ZERO, max.to_pos2());
+        interpolate_bilinear(&source, pixel.x, pixel.y).unwrap_or(Rgba([0, 0, 0, 0]))
+    });
+    DynamicImage::ImageRgba8(rectified)
+}
+
+/// The straightened image and, when it is too large for one texture, its tile pyramid.
+pub struct RectifiedImage {
+    pub image: Arc<DynamicImage>,
+    pub levels: Option<Vec<Arc<DynamicImage>>>,
+}
+
+/// A perspective crop straightened on its own thread, a large scan would freeze the window for seconds.
+pub struct RectifyJob {
+    receiver: Receiver<RectifiedImage>,
+}
+
+impl RectifyJob {
+    pub fn spawn(ctx: &egui::Context, image: Arc<DynamicImage>, quad: Quad, size: (u32, u32), max_texture_side: u32) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let ctx = ctx.clone();
+        thread::Builder::new()
+            .name("perspective-crop".to_string())
+            .spawn(move || {
+                let image = Arc::new(rectify(&image, quad, size));
+                let levels = needs_tiles(image.width(), image.height(), max_texture_side).then(|| build_levels(&image));
+                // The job is gone when the app dropped it, nobody waits for the result then
+                if sender.send(RectifiedImage { image, levels }).is_ok() {
+                    ctx.request_repaint();
+                }
+            })
+            .expect("failed to spawn the perspective crop thread");
+        Self { receiver }
+    }
+
+    /// The result once the thread is done, it is handed out only once.
+    pub fn try_take(&self) -> Option<RectifiedImage> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::gradient_image;
+
+    const TRAPEZOID: Quad = [Pos2::new(20., 10.), Pos2::new(80., 10.), Pos2::new(100., 60.), Pos2::new(0., 60.)];
+
+    fn assert_near(a: Pos2, b: Pos2) {
+        assert!((a - b).length() < 1e-3, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn the_full_quad_maps_onto_itself() {
+        let homography = Homography::square_to_quad(FULL_QUAD);
+        for uv in [Pos2::new(0.25, 0.75), Pos2::new(0.5, 0.5), Pos2::new(1., 0.)] {
+            assert_near(homography.map(uv), uv);
+        }
+    }
+
+    #[test]
+    fn square_corners_land_on_the_quad_corners() {
+        for quad in [TRAPEZOID, [Pos2::new(0., 0.), Pos2::new(40., 10.), Pos2::new(50., 40.), Pos2::new(10., 30.)]] {
+            let homography = Homography::square_to_quad(quad);
+            for (corner, expected) in FULL_QUAD.iter().zip(quad) {
+                assert_near(homography.map(*corner), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn the_square_center_lands_where_the_diagonals_cross() {
+        // Perspective keeps lines straight, so the middle of the square goes to the crossing of the quad diagonals,
+        // which for this trapezoid is not the average of its corners
+        let center = Homography::square_to_quad(TRAPEZOID).map(Pos2::new(0.5, 0.5));
+        assert_near(center, Pos2::new(50., 10. + 50. * 60. / 160.));
+    }
+
+    #[test]
+    fn only_convex_quads_can_be_straightened() {
+        assert!(is_convex_quad(FULL_QUAD));
+        assert!(is_convex_quad(TRAPEZOID));
+        let [a, b, c, d] = TRAPEZOID;
+        assert!(!is_convex_quad([a, b, d, c]), "crossing sides");
+        assert!(!is_convex_quad([a, Pos2::new(50., 40.), c, d]), "dented");
+        assert!(!is_convex_quad([a, a, c, d]), "collapsed corner");
+    }
+
+    #[test]
+    fn the_rectified_size_keeps_the_longer_sides() {
+        assert_eq!(rectified_size(TRAPEZOID), (100, 54));
+        assert_eq!(rectified_size(FULL_QUAD), (1, 1));
+    }
+
+    #[test]
+    fn an_upright_quad_is_cut_out_unchanged() {
+        let image = gradient_image(40, 30);
+        let quad = [Pos2::new(5., 8.), Pos2::new(25., 8.), Pos2::new(25., 20.), Pos2::new(5., 20.)];
+        let rectified = rectify(&image, quad, (20, 12));
+        assert_eq!(rectified.to_rgba8(), image.crop_imm(5, 8, 20, 12).to_rgba8());
+        let whole = [Pos2::new(0., 0.), Pos2::new(40., 0.), Pos2::new(40., 30.), Pos2::new(0., 30.)];
+        assert_eq!(rectify(&image, whole, (40, 30)).to_rgba8(), image.to_rgba8());
+    }
+}
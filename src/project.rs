@@ -0,0 +1,370 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::resource::*;
+
+pub const PROJECT_VERSION: u32 = 1;
+pub const PROJECT_EXTENSION: &str = "imgboard";
+pub const BUNDLE_EXTENSION: &str = "imgbundle";
+const BUNDLE_MANIFEST_NAME: &str = "project.json";
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectManifest {
+    pub version: u32,
+    pub board_rect: egui::Rect,
+    #[serde(default)]
+    pub background: BoardBackground,
+    pub layers: Vec<LayerManifest>,
+    #[serde(default)]
+    pub groups: Vec<LayerGroup>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LayerManifest {
+    pub file_path: PathBuf,
+    pub transform: ImageTranforms,
+    #[serde(default)]
+    pub mask: LayerMask,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_visible")]
+    pub is_visible: bool,
+    #[serde(default)]
+    pub is_locked: bool,
+    #[serde(default)]
+    pub group: Option<u64>,
+}
+
+impl LayerManifest {
+    pub fn new(file_path: PathBuf, layer: &OutputTexture) -> Self {
+        Self {
+            file_path,
+            transform: layer.transform,
+            mask: layer.mask.clone(),
+            name: layer.name.clone(),
+            is_visible: layer.is_visible,
+            is_locked: layer.is_locked,
+            group: layer.group,
+        }
+    }
+}
+
+/// Projects saved before layers could be hidden show all of them.
+fn default_visible() -> bool {
+    true
+}
+
+/// A bundle layer with the embedded encoded bytes, decoded later by the import queue.
+pub type BundledLayer = (LayerManifest, Arc<[u8]>);
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    UnsupportedVersion(u32),
+    UnsavedLayer,
+    MissingImages(Vec<PathBuf>),
+    Encode(image::ImageError),
+    Bundle(zip::result::ZipError),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectError::Io(err) => write!(f, "unable to access the project file: {err}"),
+            ProjectError::Parse(err) => write!(f, "the project file is damaged: {err}"),
+            ProjectError::UnsupportedVersion(version) => write!(f, "project version {version} is newer than this app supports ({PROJECT_VERSION})"),
+            ProjectError::UnsavedLayer => write!(f, "a layer has been edited or has no source file to reference, save it as a bundle instead"),
+            ProjectError::MissingImages(paths) => {
+                write!(f, "referenced images are missing:")?;
+                for path in paths {
+                    write!(f, "\n{}", path.display())?;
+                }
+                Ok(())
+            }
+            ProjectError::Encode(err) => write!(f, "unable to encode a layer for the bundle: {err}"),
+            ProjectError::Bundle(err) => write!(f, "the bundle is damaged: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+impl From<io::Error> for ProjectError {
+    fn from(err: io::Error) -> Self {
+        ProjectError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for ProjectError {
+    fn from(err: serde_json::Error) -> Self {
+        ProjectError::Parse(err)
+    }
+}
+
+impl From<zip::result::ZipError> for ProjectError {
+    fn from(err: zip::result::ZipError) -> Self {
+        ProjectError::Bundle(err)
+    }
+}
+
+impl ProjectManifest {
+    pub fn from_layers(layers: &[OutputTexture], groups: &[LayerGroup], board_rect: egui::Rect, background: BoardBackground) -> Result<Self, ProjectError> {
+        let layers = layers
+            .iter()
+            .map(|layer| {
+                let file_path = layer.file_path.clone().ok_or(ProjectError::UnsavedLayer)?;
+                Ok(LayerManifest::new(file_path, layer))
+            })
+            .collect::<Result<Vec<_>, ProjectError>>()?;
+        Ok(Self { version: PROJECT_VERSION, board_rect, background, layers, groups: groups.to_vec() })
+    }
+}
+
+/// Writes the manifest as json, image paths inside the project folder are stored relative to it.
+pub fn write_project(path: &Path, manifest: &ProjectManifest) -> Result<(), ProjectError> {
+    let project_dir = path.parent().unwrap_or(Path::new(""));
+    let layers = manifest
+        .layers
+        .iter()
+        .map(|layer| LayerManifest {
+            file_path: layer.file_path.strip_prefix(project_dir).map(Path::to_path_buf).unwrap_or_else(|_| layer.file_path.clone()),
+            ..layer.clone()
+        })
+        .collect();
+    let stored = ProjectManifest { version: manifest.version, board_rect: manifest.board_rect, background: manifest.background, layers, groups: manifest.groups.clone() };
+    fs::write(path, serde_json::to_string_pretty(&stored)?)?;
+    Ok(())
+}
+
+pub fn read_project(path: &Path) -> Result<ProjectManifest, ProjectError> {
+    let mut manifest: ProjectManifest = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if manifest.version > PROJECT_VERSION {
+        return Err(ProjectError::UnsupportedVersion(manifest.version));
+    }
+    let project_dir = path.parent().unwrap_or(Path::new(""));
+    for layer in manifest.layers.iter_mut() {
+        if layer.file_path.is_relative() {
+            layer.file_path = project_dir.join(&layer.file_path);
+        }
+    }
+    Ok(manifest)
+}
+
+/// Checks that every referenced image is still there before any is decoded, all missing files are reported together.
+pub fn find_missing_images(manifest: &ProjectManifest) -> Result<(), ProjectError> {
+    let missing: Vec<PathBuf> = manifest.layers.iter().filter(|layer| !layer.file_path.is_file()).map(|layer| layer.file_path.clone()).collect();
+    if !missing.is_empty() {
+        return Err(ProjectError::MissingImages(missing));
+    }
+    Ok(())
+}
+
+/// Writes a zip bundle holding the manifest and the encoded bytes of every layer, so it opens without the source folders.
+pub fn write_bundle(path: &Path, layers: &[OutputTexture], groups: &[LayerGroup], board_rect: egui::Rect, background: BoardBackground) -> Result<(), ProjectError> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    // Image entries are already compressed
+    let image_options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut manifest_layers = Vec::with_capacity(layers.len());
+    for (idx, layer) in layers.iter().enumerate() {
+        let (bytes, extension) = layer_source_bytes(layer)?;
+        let entry_name = format!("images/{idx:04}.{extension}");
+        zip.start_file(entry_name.as_str(), image_options)?;
+        zip.write_all(&bytes)?;
+        manifest_layers.push(LayerManifest::new(PathBuf::from(entry_name), layer));
+    }
+    let manifest = ProjectManifest { version: PROJECT_VERSION, board_rect, background, layers: manifest_layers, groups: groups.to_vec() };
+    zip.start_file(BUNDLE_MANIFEST_NAME, SimpleFileOptions::default())?;
+    zip.write_all(serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Reads the manifest and the embedded bytes of every layer, all missing entries are reported together.
+pub fn read_bundle(path: &Path) -> Result<(ProjectManifest, Vec<BundledLayer>), ProjectError> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let mut manifest_json = String::new();
+    archive.by_name(BUNDLE_MANIFEST_NAME)?.read_to_string(&mut manifest_json)?;
+    let manifest: ProjectManifest = serde_json::from_str(&manifest_json)?;
+    if manifest.version > PROJECT_VERSION {
+        return Err(ProjectError::UnsupportedVersion(manifest.version));
+    }
+    let mut layers = Vec::with_capacity(manifest.layers.len());
+    let mut missing = Vec::new();
+    for layer in manifest.layers.iter() {
+        let entry_name = layer.file_path.to_string_lossy().replace('\\', "/");
+        let mut bytes = Vec::new();
+        match archive.by_name(&entry_name) {
+            Ok(mut entry) => entry.read_to_end(&mut bytes)?,
+            Err(zip::result::ZipError::FileNotFound) => {
+                missing.push(layer.file_path.clone());
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        layers.push((layer.clone(), Arc::from(bytes)));
+    }
+    if !missing.is_empty() {
+        return Err(ProjectError::MissingImages(missing));
+    }
+    Ok((manifest, layers))
+}
+
+/// Prefers the original encoded bytes of a layer and falls back to encoding its retained image as png.
+fn layer_source_bytes(layer: &OutputTexture) -> Result<(Arc<[u8]>, String), ProjectError> {
+    let extension = layer
+        .file_path
+        .as_ref()
+        .and_then(|path| path.extension())
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase);
+    if let Some(bytes) = layer.source_bytes.as_ref() {
+        let extension = extension.or_else(|| image::guess_format(bytes).ok().and_then(|format| format.extensions_str().first().map(|ext| ext.to_string())));
+        return Ok((bytes.clone(), extension.unwrap_or_else(|| "png".to_string())));
+    }
+    if let Some(path) = layer.file_path.as_ref()
+        && let Ok(bytes) = fs::read(path) {
+        return Ok((Arc::from(bytes), extension.unwrap_or_else(|| "png".to_string())));
+    }
+    let image = layer.image.as_ref().ok_or(ProjectError::UnsavedLayer)?;
+    let mut bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png).map_err(ProjectError::Encode)?;
+    Ok((Arc::from(bytes), "png".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    const BOARD_RECT: egui::Rect = egui::Rect::from_min_max(egui::Pos2::ZERO, egui::Pos2::new(400., 300.));
+
+    fn saved_layer(ctx: &egui::Context, name: &str, file_path: PathBuf) -> OutputTexture {
+        OutputTexture { file_path: Some(file_path), ..test_layer(ctx, name) }
+    }
+
+    #[test]
+    fn a_project_round_trips_with_paths_relative_to_it() {
+        let ctx = egui::Context::default();
+        let dir = scratch_dir("project");
+        let outside = scratch_dir("project_outside");
+        let inside_path = dir.join("images").join("inside.png");
+        let outside_path = outside.join("outside.png");
+        let mut layers = vec![saved_layer(&ctx, "inside", inside_path.clone()), saved_layer(&ctx, "outside", outside_path.clone())];
+        layers[0].transform.pos = egui::Pos2::new(12., -7.);
+        layers[1].is_visible = false;
+        layers[1].group = Some(1);
+        let groups = vec![LayerGroup::new(1, None)];
+        let manifest = ProjectManifest::from_layers(&layers, &groups, BOARD_RECT, BoardBackground::default()).unwrap();
+        let project_path = dir.join(format!("board.{PROJECT_EXTENSION}"));
+        write_project(&project_path, &manifest).unwrap();
+
+        let stored: serde_json::Value = serde_json::from_str(&fs::read_to_string(&project_path).unwrap()).unwrap();
+        let stored_path = |idx: usize| PathBuf::from(stored["layers"][idx]["file_path"].as_str().unwrap());
+        assert_eq!(stored_path(0), Path::new("images").join("inside.png"));
+        assert_eq!(stored_path(1), outside_path);
+
+        let read = read_project(&project_path).unwrap();
+        assert_eq!(read.layers.iter().map(|layer| layer.file_path.clone()).collect::<Vec<_>>(), [inside_path, outside_path]);
+        assert_eq!(read.layers[0].transform, layers[0].transform);
+        assert_eq!(read.layers[0].name, "inside");
+        assert!(!read.layers[1].is_visible && read.layers[1].group == Some(1));
+        assert_eq!(read.groups, groups);
+        assert_eq!(read.board_rect, BOARD_RECT);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn a_newer_project_version_is_rejected() {
+        let dir = scratch_dir("project_version");
+        let project_path = dir.join(format!("board.{PROJECT_EXTENSION}"));
+        let manifest = ProjectManifest { version: PROJECT_VERSION + 1, board_rect: BOARD_RECT, background: BoardBackground::default(), layers: Vec::new(), groups: Vec::new() };
+        write_project(&project_path, &manifest).unwrap();
+        assert!(matches!(read_project(&project_path), Err(ProjectError::UnsupportedVersion(version)) if version == PROJECT_VERSION + 1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn every_missing_image_is_listed() {
+        let ctx = egui::Context::default();
+        let dir = scratch_dir("project_missing");
+        let present = dir.join("present.png");
+        gradient_image(4, 4).save(&present).unwrap();
+        let layers = [
+            saved_layer(&ctx, "gone", dir.join("gone.png")),
+            saved_layer(&ctx, "present", present),
+            saved_layer(&ctx, "also gone", dir.join("also_gone.png")),
+        ];
+        let manifest = ProjectManifest::from_layers(&layers, &[], BOARD_RECT, BoardBackground::default()).unwrap();
+        match find_missing_images(&manifest) {
+            Err(ProjectError::MissingImages(missing)) => assert_eq!(missing, [dir.join("gone.png"), dir.join("also_gone.png")]),
+            _ => panic!("expected the missing images"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_bundle_reopens_after_the_sources_are_gone() {
+        let ctx = egui::Context::default();
+        let dir = scratch_dir("bundle");
+        let file_path = dir.join("photo.png");
+        gradient_image(6, 4).save(&file_path).unwrap();
+        let mut jpeg_bytes = Vec::new();
+        image::DynamicImage::ImageRgb8(gradient_image(5, 3).to_rgb8()).write_to(&mut Cursor::new(&mut jpeg_bytes), ImageFormat::Jpeg).unwrap();
+        let layers = [
+            OutputTexture { file_path: Some(file_path.clone()), ..test_image_layer(&ctx, "photo", gradient_image(6, 4)) },
+            OutputTexture { source_bytes: Some(Arc::from(jpeg_bytes)), ..test_image_layer(&ctx, "imported", gradient_image(5, 3)) },
+            test_image_layer(&ctx, "pasted", gradient_image(2, 7)),
+        ];
+        let bundle_path = dir.join(format!("board.{BUNDLE_EXTENSION}"));
+        write_bundle(&bundle_path, &layers, &[], BOARD_RECT, BoardBackground::default()).unwrap();
+        fs::remove_file(&file_path).unwrap();
+
+        let (manifest, bundled) = read_bundle(&bundle_path).unwrap();
+        let entries: Vec<_> = bundled.iter().map(|(layer, _)| layer.file_path.clone()).collect();
+        assert_eq!(entries, [PathBuf::from("images/0000.png"), PathBuf::from("images/0001.jpg"), PathBuf::from("images/0002.png")]);
+        let sizes: Vec<_> = bundled.iter().map(|(_, bytes)| {
+            let decoded = crate::import::decode_image_bytes(bytes).unwrap().image;
+            (decoded.width(), decoded.height())
+        }).collect();
+        assert_eq!(sizes, [(6, 4), (5, 3), (2, 7)]);
+        assert_eq!(manifest.layers.iter().map(|layer| layer.name.as_str()).collect::<Vec<_>>(), ["photo", "imported", "pasted"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_bundle_without_an_image_entry_reports_it_missing() {
+        let ctx = egui::Context::default();
+        let dir = scratch_dir("bundle_missing");
+        let layers = [test_image_layer(&ctx, "kept", gradient_image(2, 2)), test_image_layer(&ctx, "lost", gradient_image(3, 3))];
+        let bundle_path = dir.join(format!("board.{BUNDLE_EXTENSION}"));
+        write_bundle(&bundle_path, &layers, &[], BOARD_RECT, BoardBackground::default()).unwrap();
+
+        // Copies the bundle without the second image
+        let damaged_path = dir.join(format!("damaged.{BUNDLE_EXTENSION}"));
+        let mut archive = ZipArchive::new(File::open(&bundle_path).unwrap()).unwrap();
+        let mut damaged = ZipWriter::new(File::create(&damaged_path).unwrap());
+        for idx in 0..archive.len() {
+            let entry = archive.by_index(idx).unwrap();
+            if entry.name() != "images/0001.png" {
+                damaged.raw_copy_file(entry).unwrap();
+            }
+        }
+        damaged.finish().unwrap();
+
+        match read_bundle(&damaged_path) {
+            Err(ProjectError::MissingImages(missing)) => assert_eq!(missing, [PathBuf::from("images/0001.png")]),
+            _ => panic!("expected the missing image"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
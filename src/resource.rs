@@ -1,7 +1,12 @@
 use std::hash::{Hash, Hasher};
 use egui::{Pos2, TextureHandle, TextureId, Vec2};
 use image::DynamicImage;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::perspective::{Quad, FULL_QUAD};
+use crate::tiles::TiledImage;
 
 pub trait FitIn {
     fn is_fit_in(&self, container: egui::Rect) -> bool;
@@ -9,11 +14,11 @@ pub trait FitIn {
 
 impl FitIn for egui::Rect {
     fn is_fit_in(&self, container: egui::Rect) -> bool {
-        !(self.max.x < container.min.x) && !(self.max.y < container.min.y) && !{self.min.x > container.max.x} && !(self.min.y > container.max.y)
+        self.max.x >= container.min.x && self.max.y >= container.min.y && self.min.x <= container.max.x && self.min.y <= container.max.y
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
 pub struct CropRect {
     pub top: f32,
     pub left: f32,
@@ -42,21 +47,28 @@ impl Default for CropRect {
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
 pub struct ImageTranforms {
     pub scale: f32,
+    /// Horizontal stretch on top of `scale`, 1 keeps the aspect ratio of the image.
+    pub aspect_stretch: f32,
     pub original_scale: f32,
     pub pos: Pos2,
     pub rotation: f32,
     pub opacity: f32,
     pub croped: Option<CropRect>,
-    pub size: Vec2
+    pub size: Vec2,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub quarter_turns: u8
 }
 
 impl Hash for ImageTranforms {
     fn hash<H: Hasher>(&self, state: &mut H) {
         // Convert f32 to bits for hashing since f32 doesn't implement Hash
         self.scale.to_bits().hash(state);
+        self.aspect_stretch.to_bits().hash(state);
         self.original_scale.to_bits().hash(state);
         self.pos.x.to_bits().hash(state);
         self.pos.y.to_bits().hash(state);
@@ -65,6 +77,9 @@ impl Hash for ImageTranforms {
         self.size.x.to_bits().hash(state);
         self.size.y.to_bits().hash(state);
         self.croped.hash(state);
+        self.flip_horizontal.hash(state);
+        self.flip_vertical.hash(state);
+        self.quarter_turns.hash(state);
     }
 }
 
@@ -90,7 +105,117 @@ impl Default for ImageProps {
 
 impl Default for ImageTranforms {
     fn default() -> Self {
-        Self { scale: 1., original_scale: 1.,pos: Pos2::ZERO, rotation: 0.0, opacity: 1.0, croped: None, size: Vec2::ZERO }
+        Self { scale: 1., aspect_stretch: 1., original_scale: 1.,pos: Pos2::ZERO, rotation: 0.0, opacity: 1.0, croped: None, size: Vec2::ZERO, flip_horizontal: false, flip_vertical: false, quarter_turns: 0 }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OrientationOp {
+    FlipHorizontal,
+    FlipVertical,
+    RotateClockwise,
+    RotateCounterClockwise,
+    Rotate180
+}
+
+impl CropRect {
+    pub fn scaled(&self, factor: f32) -> Self {
+        Self { top: self.top * factor, left: self.left * factor, bottom: self.bottom * factor, right: self.right * factor }
+    }
+}
+
+// The source image is flipped first and then turned clockwise by `quarter_turns`,
+// croping and every size on the board are expressed in that oriented space.
+impl ImageTranforms {
+    /// The same layer as seen through a board zoom, board units become screen pixels.
+    pub fn zoomed(&self, zoom: f32) -> Self {
+        Self {
+            pos: (self.pos.to_vec2() * zoom).to_pos2(),
+            original_scale: self.original_scale * zoom,
+            croped: self.croped.map(|croped| croped.scaled(zoom)),
+            ..*self
+        }
+    }
+
+    /// Board units per unscaled board unit along each axis.
+    pub fn axis_scale(&self) -> Vec2 {
+        Vec2::new(self.scale * self.aspect_stretch, self.scale)
+    }
+
+    pub fn has_orientation(&self) -> bool {
+        self.flip_horizontal || self.flip_vertical || !self.quarter_turns.is_multiple_of(4)
+    }
+
+    pub fn oriented_size(&self, image_size: Vec2) -> Vec2 {
+        if self.quarter_turns % 2 == 1 {
+            Vec2::new(image_size.y, image_size.x)
+        } else {
+            image_size
+        }
+    }
+
+    /// Maps a uv in the oriented image back to the uv in the source texture.
+    pub fn oriented_uv(&self, uv: Pos2) -> Pos2 {
+        let mut source_uv = uv;
+        for _ in 0..self.quarter_turns % 4 {
+            source_uv = Pos2::new(source_uv.y, 1. - source_uv.x);
+        }
+        if self.flip_horizontal {
+            source_uv.x = 1. - source_uv.x;
+        }
+        if self.flip_vertical {
+            source_uv.y = 1. - source_uv.y;
+        }
+        source_uv
+    }
+
+    /// Inverse of `oriented_uv`, maps a uv in the source texture to the uv in the oriented image.
+    pub fn source_to_oriented_uv(&self, source_uv: Pos2) -> Pos2 {
+        let mut uv = source_uv;
+        if self.flip_horizontal {
+            uv.x = 1. - uv.x;
+        }
+        if self.flip_vertical {
+            uv.y = 1. - uv.y;
+        }
+        for _ in 0..self.quarter_turns % 4 {
+            uv = Pos2::new(1. - uv.y, uv.x);
+        }
+        uv
+    }
+
+    /// Applies a flip or a quarter turn as seen on the board, keeping the croped area on the same part of the image.
+    pub fn apply_orientation_op(&mut self, op: OrientationOp) {
+        match op {
+            OrientationOp::FlipHorizontal | OrientationOp::FlipVertical => {
+                // A flip of the displayed image is the other flip of the source when it is turned sideways
+                let is_horizontal = (op == OrientationOp::FlipHorizontal) == self.quarter_turns.is_multiple_of(2);
+                if is_horizontal {
+                    self.flip_horizontal = !self.flip_horizontal;
+                } else {
+                    self.flip_vertical = !self.flip_vertical;
+                }
+                if let Some(crop) = self.croped.as_mut() {
+                    if op == OrientationOp::FlipHorizontal {
+                        *crop = CropRect { left: -crop.right, right: -crop.left, ..*crop };
+                    } else {
+                        *crop = CropRect { top: -crop.bottom, bottom: -crop.top, ..*crop };
+                    }
+                }
+            }
+            OrientationOp::RotateClockwise => self.turn_clockwise(1),
+            OrientationOp::Rotate180 => self.turn_clockwise(2),
+            OrientationOp::RotateCounterClockwise => self.turn_clockwise(3),
+        }
+    }
+
+    fn turn_clockwise(&mut self, turns: u8) {
+        for _ in 0..turns {
+            self.quarter_turns = (self.quarter_turns + 1) % 4;
+            if let Some(crop) = self.croped.as_mut() {
+                *crop = CropRect { top: crop.left, right: -crop.top, bottom: crop.right, left: -crop.bottom };
+            }
+        }
     }
 }
 
@@ -108,27 +233,230 @@ impl ActiveImage {
     }
 }
 
+/// A folder in the Images panel. The layers of a group always sit next to each other in the layer list,
+/// so the tree is the flat draw order with groups around runs of it.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct LayerGroup {
+    pub id: u64,
+    pub name: String,
+    /// The group this one is nested in.
+    pub parent: Option<u64>,
+    pub is_collapsed: bool,
+    pub is_visible: bool,
+    pub is_locked: bool,
+    /// Multiplied into the opacity of every layer inside.
+    pub opacity: f32,
+}
+
+impl LayerGroup {
+    pub fn new(id: u64, parent: Option<u64>) -> Self {
+        Self { id, name: format!("Group {id}"), parent, is_collapsed: false, is_visible: true, is_locked: false, opacity: 1. }
+    }
+}
+
+/// Layers picked together on the board or in the Images panel, the active layer is one of them.
+/// Moving, scaling and opacity changes of the active layer are passed on to the others.
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct BoardSelection {
+    pub layers: Vec<TextureId>,
+    /// Where a drag over empty board space started and where the pointer is now, in board units.
+    pub rubber_band: Option<(Pos2, Pos2)>,
+}
+
+impl BoardSelection {
+    pub fn contains(&self, id: TextureId) -> bool {
+        self.layers.contains(&id)
+    }
+
+    pub fn is_group(&self) -> bool {
+        self.layers.len() > 1
+    }
+}
+
+/// Details read from the image file on import, shown in the image info panel.
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct ImageMetadata {
+    pub format: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub camera: Option<String>,
+    pub date_taken: Option<String>,
+    pub dpi: Option<(f32, f32)>,
+    pub color_profile: Option<String>,
+    pub exif_orientation: Option<u8>
+}
+
+const MASK_ELLIPSE_POINTS: usize = 64;
+const MASK_CORNER_POINTS: usize = 8;
+
+/// Shape the croped part of a layer is cut to. Shapes are laid out in uv of the croped rect,
+/// so they follow the crop, the scale and the rotation of the layer.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum LayerMask {
+    #[default]
+    Rectangle,
+    Ellipse,
+    /// Corner radius as a fraction of the shorter side, at most half of it.
+    RoundedRect(f32),
+    /// Freeform outline in drawing order.
+    Polygon(Vec<Pos2>),
+}
+
+impl Hash for LayerMask {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            LayerMask::Rectangle | LayerMask::Ellipse => {}
+            LayerMask::RoundedRect(radius) => radius.to_bits().hash(state),
+            LayerMask::Polygon(points) => {
+                for point in points {
+                    point.x.to_bits().hash(state);
+                    point.y.to_bits().hash(state);
+                }
+            }
+        }
+    }
+}
+
+impl LayerMask {
+    pub fn label(&self) -> &'static str {
+        match self {
+            LayerMask::Rectangle => "Rectangle",
+            LayerMask::Ellipse => "Ellipse",
+            LayerMask::RoundedRect(_) => "Rounded rectangle",
+            LayerMask::Polygon(_) => "Polygon",
+        }
+    }
+
+    /// A hexagon to start a freeform mask from.
+    pub fn default_polygon() -> Self {
+        let points = (0..6)
+            .map(|idx| {
+                let angle = std::f32::consts::TAU * idx as f32 / 6.;
+                Pos2::new(0.5 + 0.5 * angle.cos(), 0.5 + 0.5 * angle.sin())
+            })
+            .collect();
+        LayerMask::Polygon(points)
+    }
+
+    /// The mask outline in uv, `size` is the croped rect in any unit and keeps rounded corners round.
+    pub fn outline(&self, size: Vec2) -> Vec<Pos2> {
+        match self {
+            LayerMask::Rectangle => vec![Pos2::ZERO, Pos2::new(1., 0.), Pos2::new(1., 1.), Pos2::new(0., 1.)],
+            LayerMask::Ellipse => (0..MASK_ELLIPSE_POINTS)
+                .map(|idx| {
+                    let angle = std::f32::consts::TAU * idx as f32 / MASK_ELLIPSE_POINTS as f32;
+                    Pos2::new(0.5 + 0.5 * angle.cos(), 0.5 + 0.5 * angle.sin())
+                })
+                .collect(),
+            LayerMask::RoundedRect(radius) => {
+                let radius = radius.clamp(0., 0.5) * size.x.min(size.y);
+                let radius_uv = Vec2::new(radius / size.x.max(f32::EPSILON), radius / size.y.max(f32::EPSILON));
+                // Corner centers clockwise from the bottom right, each followed by its quarter arc
+                let centers = [
+                    Pos2::new(1. - radius_uv.x, 1. - radius_uv.y),
+                    Pos2::new(radius_uv.x, 1. - radius_uv.y),
+                    Pos2::new(radius_uv.x, radius_uv.y),
+                    Pos2::new(1. - radius_uv.x, radius_uv.y),
+                ];
+                centers
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(corner, center)| {
+                        (0..=MASK_CORNER_POINTS).map(move |step| {
+                            let angle = std::f32::consts::FRAC_PI_2 * (corner as f32 + step as f32 / MASK_CORNER_POINTS as f32);
+                            *center + Vec2::new(angle.cos(), angle.sin()) * radius_uv
+                        })
+                    })
+                    .collect()
+            }
+            LayerMask::Polygon(points) => points.clone(),
+        }
+    }
+
+    /// Whether `uv` is inside the mask, `outline` is what `outline` returned for the same size
+    /// so it can be reused for every pixel of a layer.
+    pub fn contains(&self, uv: Pos2, outline: &[Pos2]) -> bool {
+        if !(0. ..=1.).contains(&uv.x) || !(0. ..=1.).contains(&uv.y) {
+            return false;
+        }
+        match self {
+            LayerMask::Rectangle => true,
+            LayerMask::Ellipse => (uv - Pos2::new(0.5, 0.5)).length_sq() <= 0.25,
+            _ => {
+                // Even odd rule on the outline
+                let mut is_inside = false;
+                for idx in 0..outline.len() {
+                    let start = outline[idx];
+                    let end = outline[(idx + 1) % outline.len()];
+                    if (start.y > uv.y) != (end.y > uv.y) {
+                        let cross_x = start.x + (uv.y - start.y) / (end.y - start.y) * (end.x - start.x);
+                        if uv.x < cross_x {
+                            is_inside = !is_inside;
+                        }
+                    }
+                }
+                is_inside
+            }
+        }
+    }
+}
+
 #[derive(Clone, PartialEq)]
 pub struct OutputTexture {
-    pub image: Option<DynamicImage>,
+    pub image: Option<Arc<DynamicImage>>,
     pub original_scale: f32,
     pub texture_handle: Option<TextureHandle>,
     pub file_path: Option<PathBuf>,
+    pub source_bytes: Option<Arc<[u8]>>,
     pub image_ratio: f32,
     pub is_active: bool,
     pub transform: ImageTranforms,
-    pub image_props: ImageProps
+    pub image_props: ImageProps,
+    pub metadata: ImageMetadata,
+    pub tiles: Option<Arc<TiledImage>>,
+    pub mask: LayerMask,
+    pub name: String,
+    /// Hidden layers are neither drawn nor exported.
+    pub is_visible: bool,
+    /// Locked layers can still be selected but not moved or transformed on the board.
+    pub is_locked: bool,
+    /// The innermost group holding the layer, `None` for layers at the top of the tree.
+    pub group: Option<u64>
+}
+
+impl OutputTexture {
+    /// Size of the source image in pixels, the texture of a tiled layer is only a preview.
+    pub fn image_size(&self) -> Vec2 {
+        if self.transform.size.x > 0. && self.transform.size.y > 0. {
+            self.transform.size
+        } else {
+            self.texture_handle.as_ref().map_or(Vec2::ZERO, |texture| texture.size_vec2())
+        }
+    }
+
+    /// The name a layer starts with, the file stem when it came from a file.
+    pub fn default_name(file_path: Option<&Path>) -> String {
+        file_path
+            .and_then(|path| path.file_stem())
+            .map_or_else(|| "Untitled".to_string(), |stem| stem.to_string_lossy().to_string())
+    }
 }
 
 impl Hash for OutputTexture {
     fn hash<H: Hasher>(&self, state: &mut H) {
         // Hash file_path if it exists (skip the image and texture_handle as they're not hashable)
-        self.file_path.as_ref().map(|p| p.hash(state));
+        if let Some(p) = self.file_path.as_ref() { p.hash(state) }
         self.original_scale.to_bits().hash(state);
         self.image_ratio.to_bits().hash(state);
         self.is_active.hash(state);
         self.transform.hash(state);
         self.image_props.hash(state);
+        self.mask.hash(state);
+        self.name.hash(state);
+        self.is_visible.hash(state);
+        self.is_locked.hash(state);
+        self.group.hash(state);
     }
 }
 
@@ -140,17 +468,25 @@ impl Default for OutputTexture {
             image: None,
             texture_handle: None,
             file_path: None,
+            source_bytes: None,
             image_ratio: 1.,
             original_scale: 1.,
             is_active: true,
             transform: ImageTranforms::default(),
-            image_props: ImageProps::default()
+            image_props: ImageProps::default(),
+            metadata: ImageMetadata::default(),
+            tiles: None,
+            mask: LayerMask::default(),
+            name: String::new(),
+            is_visible: true,
+            is_locked: false,
+            group: None
         }
     }
 }
 
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct ImagePreviewPad {
     
     pub texture: OutputTexture,
@@ -163,20 +499,110 @@ impl Hash for ImagePreviewPad {
     }
 }
 
-impl Default for ImagePreviewPad {
+
+
+pub const MIN_BOARD_SIZE: f32 = 1.;
+pub const MAX_BOARD_SIZE: f32 = 16384.;
+
+/// Common document sizes offered next to the numeric board size, in pixels.
+pub const BOARD_PRESETS: [(&str, Vec2); 8] = [
+    ("HD 720p", Vec2::new(1280., 720.)),
+    ("Full HD 1080p", Vec2::new(1920., 1080.)),
+    ("4K UHD", Vec2::new(3840., 2160.)),
+    ("A4 @ 300 DPI", Vec2::new(2480., 3508.)),
+    ("A4 landscape @ 300 DPI", Vec2::new(3508., 2480.)),
+    ("Instagram square", Vec2::new(1080., 1080.)),
+    ("Instagram portrait", Vec2::new(1080., 1350.)),
+    ("Story 9:16", Vec2::new(1080., 1920.)),
+];
+
+/// What shows through where no layer covers the board, on screen and in the exported image.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum BoardBackground {
+    Color(egui::Color32),
+    Transparent,
+}
+
+impl Default for BoardBackground {
     fn default() -> Self {
-        Self {
-          
-            texture: OutputTexture::default(),
+        BoardBackground::Color(egui::Color32::from_rgb(200, 200, 200))
+    }
+}
+
+impl Hash for BoardBackground {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            BoardBackground::Color(color) => color.to_array().hash(state),
+            BoardBackground::Transparent => 0u8.hash(state),
         }
     }
 }
 
+impl BoardBackground {
+    /// Unmultiplied rgba of the export canvas.
+    pub fn to_rgba(self) -> [u8; 4] {
+        match self {
+            BoardBackground::Color(color) => color.to_srgba_unmultiplied(),
+            BoardBackground::Transparent => [0, 0, 0, 0],
+        }
+    }
+}
 
+pub const MIN_BOARD_ZOOM: f32 = 0.05;
+pub const MAX_BOARD_ZOOM: f32 = 32.;
+
+/// Zoom and pan of the board inside its viewport, the board is drawn `zoom` screen pixels per board unit
+/// with its top left corner `pan` away from the top left of the viewport.
+#[derive(Clone, Copy, PartialEq)]
+pub struct BoardView {
+    pub zoom: f32,
+    pub pan: Vec2,
+    pub is_panning: bool,
+}
+
+impl Default for BoardView {
+    fn default() -> Self {
+        Self { zoom: 1., pan: Vec2::ZERO, is_panning: false }
+    }
+}
+
+impl BoardView {
+    pub fn board_screen_rect(&self, viewport_rect: egui::Rect, board_size: Vec2) -> egui::Rect {
+        egui::Rect::from_min_size(viewport_rect.min + self.pan, board_size * self.zoom)
+    }
+
+    /// Scales around `screen_pos` so the board point below it stays in place.
+    pub fn zoom_at(&mut self, viewport_rect: egui::Rect, screen_pos: Pos2, factor: f32) {
+        let zoom = (self.zoom * factor).clamp(MIN_BOARD_ZOOM, MAX_BOARD_ZOOM);
+        let board_min = viewport_rect.min + self.pan;
+        let board_point = (screen_pos - board_min) / self.zoom;
+        self.pan = screen_pos - viewport_rect.min - board_point * zoom;
+        self.zoom = zoom;
+    }
+
+    pub fn fit(&mut self, viewport_rect: egui::Rect, board_size: Vec2) {
+        let margin = 20.;
+        let available = (viewport_rect.size() - Vec2::splat(margin * 2.)).max(Vec2::splat(1.));
+        let zoom = (available.x / board_size.x).min(available.y / board_size.y).clamp(MIN_BOARD_ZOOM, MAX_BOARD_ZOOM);
+        self.set_centered(viewport_rect, board_size, zoom);
+    }
+
+    pub fn set_centered(&mut self, viewport_rect: egui::Rect, board_size: Vec2, zoom: f32) {
+        self.zoom = zoom;
+        self.pan = (viewport_rect.size() - board_size * zoom) / 2.;
+    }
+}
+
+/// The board on screen, `image_plot_rect` is where the board is drawn this frame
+/// and follows `board_size` through the view, sizes on the board are in board units.
 #[derive(Clone, Copy)]
 pub struct MainImageRectSetting {
     pub is_dragging: bool,
     pub image_plot_rect: egui::Rect,
+    pub viewport_rect: egui::Rect,
+    pub board_size: Vec2,
+    pub background: BoardBackground,
+    pub view: BoardView,
     pub right_side_drag_pos: Pos2,
     pub right_side_drag_offset: f32,
     pub bottom_side_drag_pos: Pos2,
@@ -190,6 +616,12 @@ impl Hash for MainImageRectSetting {
         self.image_plot_rect.min.y.to_bits().hash(state);
         self.image_plot_rect.max.x.to_bits().hash(state);
         self.image_plot_rect.max.y.to_bits().hash(state);
+        self.board_size.x.to_bits().hash(state);
+        self.board_size.y.to_bits().hash(state);
+        self.background.hash(state);
+        self.view.zoom.to_bits().hash(state);
+        self.view.pan.x.to_bits().hash(state);
+        self.view.pan.y.to_bits().hash(state);
         self.right_side_drag_pos.x.to_bits().hash(state);
         self.right_side_drag_pos.y.to_bits().hash(state);
         self.right_side_drag_offset.to_bits().hash(state);
@@ -206,6 +638,10 @@ impl Default for MainImageRectSetting {
         Self {
             is_dragging: false,
             image_plot_rect: egui::Rect { min: starting_pos, max: starting_pos + size },
+            viewport_rect: egui::Rect { min: starting_pos, max: starting_pos + size },
+            board_size: size,
+            background: BoardBackground::default(),
+            view: BoardView::default(),
             right_side_drag_pos: Pos2::ZERO,
             bottom_side_drag_pos: Pos2::ZERO,
             right_side_drag_offset: 0.0,
@@ -214,13 +650,82 @@ impl Default for MainImageRectSetting {
     }
 }
 
+impl MainImageRectSetting {
+    /// Places the board for this frame inside the viewport the ui handed out.
+    pub fn update_board_rect(&mut self, viewport_rect: egui::Rect) {
+        self.viewport_rect = viewport_rect;
+        self.image_plot_rect = self.view.board_screen_rect(viewport_rect, self.board_size);
+    }
+
+    /// Changes the document size, layers keep their offsets from the board center.
+    pub fn set_board_size(&mut self, size: Vec2) {
+        self.board_size = size.round().clamp(Vec2::splat(MIN_BOARD_SIZE), Vec2::splat(MAX_BOARD_SIZE));
+        self.image_plot_rect = self.view.board_screen_rect(self.viewport_rect, self.board_size);
+    }
+
+    /// Board units relative to the board center, the space layer positions live in.
+    pub fn screen_to_board(&self, screen_pos: Pos2) -> Pos2 {
+        ((screen_pos - self.image_plot_rect.center()) / self.view.zoom).to_pos2()
+    }
+
+    pub fn board_to_screen(&self, board_pos: Pos2) -> Pos2 {
+        self.image_plot_rect.center() + board_pos.to_vec2() * self.view.zoom
+    }
+
+    /// The part of the board that can be seen and interacted with.
+    pub fn visible_board_rect(&self) -> egui::Rect {
+        self.image_plot_rect.intersect(self.viewport_rect)
+    }
+}
+
+pub const MIN_LAYER_SCALE: f32 = 0.1;
+pub const MAX_LAYER_SCALE: f32 = 10.;
+
+/// A handle around the active layer, scale handles are named by the side they sit on,
+/// -1 for left or top, 1 for right or bottom and 0 for the middle.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum TransformHandle {
+    Scale { x: i8, y: i8 },
+    Rotate,
+}
+
+pub const TRANSFORM_HANDLES: [TransformHandle; 9] = [
+    TransformHandle::Scale { x: -1, y: -1 },
+    TransformHandle::Scale { x: 0, y: -1 },
+    TransformHandle::Scale { x: 1, y: -1 },
+    TransformHandle::Scale { x: 1, y: 0 },
+    TransformHandle::Scale { x: 1, y: 1 },
+    TransformHandle::Scale { x: 0, y: 1 },
+    TransformHandle::Scale { x: -1, y: 1 },
+    TransformHandle::Scale { x: -1, y: 0 },
+    TransformHandle::Rotate,
+];
+
+/// A handle drag in progress, the new transform is always derived from the one it started with.
+#[derive(Clone, Copy, PartialEq)]
+pub struct TransformDrag {
+    pub handle: TransformHandle,
+    pub start: ImageTranforms,
+    pub start_pointer: Pos2,
+}
+
+impl Hash for TransformDrag {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.handle.hash(state);
+        self.start.hash(state);
+        self.start_pointer.x.to_bits().hash(state);
+        self.start_pointer.y.to_bits().hash(state);
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct ActiveImageSettings {
     pub transforms: ImageTranforms,
     pub props: ImageProps,
     pub croped_modified: CropedImageModified,
     pub is_dragging: bool,
-    pub drag_offset: Vec2
+    pub drag_offset: Vec2,
+    pub transform_drag: Option<TransformDrag>
 }
 
 impl Hash for ActiveImageSettings {
@@ -231,6 +736,7 @@ impl Hash for ActiveImageSettings {
         self.is_dragging.hash(state);
         self.drag_offset.x.to_bits().hash(state);
         self.drag_offset.y.to_bits().hash(state);
+        self.transform_drag.hash(state);
     }
 }
 
@@ -241,7 +747,8 @@ impl Default for ActiveImageSettings {
             props: ImageProps::default(),
             croped_modified: CropedImageModified::default(),
             is_dragging: false,
-            drag_offset: Vec2::ZERO
+            drag_offset: Vec2::ZERO,
+            transform_drag: None
         }
     }
     
@@ -281,11 +788,12 @@ impl CropedImage {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+/// A crop handle drag, the crop it started from is moved by the pointer travel since `drag_pos`.
+#[derive(Clone, Copy, PartialEq)]
 pub struct CropImageDragState {
     pub is_dragging: bool,
     pub drag_pos: Pos2, 
-    pub drag_offset: Vec2
+    pub start_crop: CropRect
 }
 
 impl Default for CropImageDragState {
@@ -293,16 +801,66 @@ impl Default for CropImageDragState {
         Self {
             is_dragging: false,
             drag_pos: Pos2::ZERO,
-            drag_offset: Vec2::ZERO
+            start_crop: CropRect::default()
         }
     }
 } 
 
+/// What the crop view edits, a rectangle kept on the layer or four corners that straighten the image into a new one.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub enum CropMode {
+    #[default]
+    Rectangle,
+    Perspective,
+}
+
+/// Aspect ratio the crop is locked to while editing.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CropAspect {
+    Free,
+    Original,
+    Ratio(f32, f32),
+    Custom(f32, f32),
+}
+
+pub const CROP_ASPECTS: [CropAspect; 6] = [
+    CropAspect::Free,
+    CropAspect::Original,
+    CropAspect::Ratio(1., 1.),
+    CropAspect::Ratio(4., 3.),
+    CropAspect::Ratio(16., 9.),
+    CropAspect::Custom(3., 2.),
+];
+
+impl CropAspect {
+    pub fn label(&self) -> String {
+        match self {
+            CropAspect::Free => "Free".to_owned(),
+            CropAspect::Original => "Original".to_owned(),
+            CropAspect::Ratio(width, height) => format!("{width}:{height}"),
+            CropAspect::Custom(..) => "Custom".to_owned(),
+        }
+    }
+
+    /// Width over height of the locked crop, `image_size` is the oriented image in pixels.
+    pub fn ratio(&self, image_size: Vec2) -> Option<f32> {
+        match *self {
+            CropAspect::Free => None,
+            CropAspect::Original => Some(image_size.x / image_size.y),
+            CropAspect::Ratio(width, height) | CropAspect::Custom(width, height) => Some(width / height),
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub struct CropedImageModified {
     pub is_modifying: bool,
     pub max_scale: f32,
     pub crop_drag_state: CropImageDragState,
+    pub aspect: CropAspect,
+    pub mode: CropMode,
+    /// Corners of the perspective crop in uv of the oriented image.
+    pub perspective: Quad,
     pub prev_modified: Option<CropRect>,
     pub current_modified: Option<CropRect>,
     pub dest_modified: Option<CropRect>
@@ -314,6 +872,9 @@ impl Default for CropedImageModified {
             is_modifying: false,
             max_scale: 1.,
             crop_drag_state: CropImageDragState::default(),
+            aspect: CropAspect::Free,
+            mode: CropMode::Rectangle,
+            perspective: FULL_QUAD,
             prev_modified: None,
             current_modified: None,
             dest_modified: None
@@ -324,6 +885,11 @@ impl Default for CropedImageModified {
 impl Hash for CropedImageModified {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.is_modifying.hash(state);
+        self.mode.hash(state);
+        for corner in self.perspective {
+            corner.x.to_bits().hash(state);
+            corner.y.to_bits().hash(state);
+        }
         self.prev_modified.hash(state);
         self.current_modified.hash(state);
     }
@@ -353,3 +919,66 @@ impl Default for ImageSortingModify {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    const OPS: [OrientationOp; 5] = [
+        OrientationOp::FlipHorizontal,
+        OrientationOp::FlipVertical,
+        OrientationOp::RotateClockwise,
+        OrientationOp::RotateCounterClockwise,
+        OrientationOp::Rotate180,
+    ];
+
+    fn apply_to_image(image: &DynamicImage, op: OrientationOp) -> DynamicImage {
+        match op {
+            OrientationOp::FlipHorizontal => image.fliph(),
+            OrientationOp::FlipVertical => image.flipv(),
+            OrientationOp::RotateClockwise => image.rotate90(),
+            OrientationOp::RotateCounterClockwise => image.rotate270(),
+            OrientationOp::Rotate180 => image.rotate180(),
+        }
+    }
+
+    #[test]
+    fn every_op_four_times_is_the_identity() {
+        let crop = CropRect { top: 3., left: 1., bottom: -2., right: -4. };
+        for op in OPS {
+            let mut transform = ImageTranforms { croped: Some(crop), ..Default::default() };
+            for _ in 0..4 {
+                transform.apply_orientation_op(op);
+            }
+            assert!(!transform.has_orientation(), "{op:?}");
+            assert_eq!(transform.croped, Some(crop), "{op:?}");
+        }
+    }
+
+    #[test]
+    fn ops_match_the_image_crate_on_top_of_any_orientation() {
+        let image = gradient_image(3, 2);
+        for first in OPS {
+            for second in OPS {
+                let mut transform = ImageTranforms::default();
+                transform.apply_orientation_op(first);
+                transform.apply_orientation_op(second);
+                let expected = apply_to_image(&apply_to_image(&image, first), second).to_rgba8();
+                assert_eq!(orient_by_transform(&image, &transform), expected, "{first:?} then {second:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn source_to_oriented_uv_inverts_oriented_uv() {
+        let uv = Pos2::new(0.2, 0.7);
+        for quarter_turns in 0..4 {
+            for (flip_horizontal, flip_vertical) in [(false, false), (true, false), (false, true), (true, true)] {
+                let transform = ImageTranforms { flip_horizontal, flip_vertical, quarter_turns, ..Default::default() };
+                let round_trip = transform.source_to_oriented_uv(transform.oriented_uv(uv));
+                assert!((round_trip - uv).length() < 1e-6, "{transform:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Layers for the unit tests, their textures live in a headless egui context.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use egui::{Color32, ColorImage, Pos2, TextureId, TextureOptions, Vec2};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+use crate::resource::*;
+
+/// A one pixel layer, enough for tests that only look at ids, order and transforms.
+pub fn test_layer(ctx: &egui::Context, name: &str) -> OutputTexture {
+    let texture_handle = ctx.load_texture(name, ColorImage::new([1, 1], vec![Color32::WHITE]), TextureOptions::default());
+    OutputTexture {
+        texture_handle: Some(texture_handle),
+        name: name.to_string(),
+        ..Default::default()
+    }
+}
+
+pub fn layer_id(layer: &OutputTexture) -> TextureId {
+    layer.texture_handle.as_ref().unwrap().id()
+}
+
+pub fn layer_names(layers: &[OutputTexture]) -> Vec<&str> {
+    layers.iter().map(|layer| layer.name.as_str()).collect()
+}
+
+/// An image where every pixel has its own color, so moved pixels can be told apart.
+pub fn gradient_image(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| Rgba([(x * 255 / width) as u8, (y * 255 / height) as u8, 128, 255])))
+}
+
+/// The image as the flips and turns of `transform` show it, sampled through `oriented_uv` at every pixel center.
+pub fn orient_by_transform(image: &DynamicImage, transform: &ImageTranforms) -> RgbaImage {
+    let size = transform.oriented_size(Vec2::new(image.width() as f32, image.height() as f32));
+    RgbaImage::from_fn(size.x as u32, size.y as u32, |x, y| {
+        let uv = Pos2::new((x as f32 + 0.5) / size.x, (y as f32 + 0.5) / size.y);
+        let source = transform.oriented_uv(uv);
+        image.get_pixel((source.x * image.width() as f32) as u32, (source.y * image.height() as f32) as u32)
+    })
+}
+
+/// A layer showing `image` at its pixel size, so board units and image pixels line up.
+pub fn test_image_layer(ctx: &egui::Context, name: &str, image: DynamicImage) -> OutputTexture {
+    let size = Vec2::new(image.width() as f32, image.height() as f32);
+    let texture_handle = ctx.load_texture(name, ColorImage::from_rgba_unmultiplied([image.width() as _, image.height() as _], image.to_rgba8().as_raw()), TextureOptions::default());
+    OutputTexture {
+        image: Some(Arc::new(image)),
+        original_scale: 1.,
+        texture_handle: Some(texture_handle),
+        image_ratio: size.x / size.y,
+        transform: ImageTranforms { size, original_scale: 1., ..Default::default() },
+        name: name.to_string(),
+        ..Default::default()
+    }
+}
+
+/// A fresh folder under the system temp folder, removed again by the test.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("img_viewer_{name}_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,213 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use egui::{ColorImage, Pos2, TextureHandle, TextureId, TextureOptions};
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+pub const TILE_SIZE: u32 = 1024;
+/// Images with a longer side are drawn from tiles, the single layer texture only holds a preview.
+pub const TILED_IMAGE_SIDE: u32 = 4096;
+/// Tiles uploaded per layer and frame, the preview covers the rest until they arrive.
+pub const TILE_UPLOADS_PER_FRAME: usize = 4;
+/// Tiles not drawn for this many frames are released by `TiledImage::evict_unused`.
+const TILE_EVICT_FRAMES: u64 = 120;
+
+pub fn needs_tiles(width: u32, height: u32, max_texture_side: u32) -> bool {
+    width.max(height) > TILED_IMAGE_SIDE.min(max_texture_side)
+}
+
+/// Halves the source until a level fits into a single tile, the first level is the source itself.
+/// Level 0 shares its allocation with the layer image, which export, copy and the bakes need at full resolution
+/// anyway, so it costs no extra memory. The smaller levels add about a third of the source on top.
+pub fn build_levels(source: &Arc<DynamicImage>) -> Vec<Arc<DynamicImage>> {
+    let mut levels = vec![source.clone()];
+    while let Some(last) = levels.last()
+        && last.width().max(last.height()) > TILE_SIZE {
+        let reduced = last.resize_exact((last.width() / 2).max(1), (last.height() / 2).max(1), FilterType::Triangle);
+        levels.push(Arc::new(reduced));
+    }
+    levels
+}
+
+pub fn color_image_from(image: &DynamicImage) -> ColorImage {
+    ColorImage::from_rgba_unmultiplied([image.width() as _, image.height() as _], image.to_rgba8().as_flat_samples().as_slice())
+}
+
+/// Everything needed to show a layer, prepared off the UI thread when possible.
+/// Large images get the smallest level as their preview texture and a pyramid to draw the tiles from.
+pub fn prepare_layer_pixels(image: &Arc<DynamicImage>, max_texture_side: u32) -> (ColorImage, Option<Vec<Arc<DynamicImage>>>) {
+    if !needs_tiles(image.width(), image.height(), max_texture_side) {
+        return (color_image_from(image), None);
+    }
+    let levels = build_levels(image);
+    (color_image_from(levels.last().unwrap()), Some(levels))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct TileKey {
+    level: usize,
+    column: u32,
+    row: u32,
+}
+
+struct CachedTile {
+    texture: TextureHandle,
+    last_used: u64,
+}
+
+/// A tile ready to draw and the part of the source image it covers, in source uv.
+pub struct VisibleTile {
+    pub texture_id: TextureId,
+    pub source_uv: egui::Rect,
+}
+
+/// Level of detail pyramid of a large image, tiles are cut and uploaded lazily when they become visible.
+pub struct TiledImage {
+    levels: Vec<Arc<DynamicImage>>,
+    cache: Mutex<HashMap<TileKey, CachedTile>>,
+}
+
+impl PartialEq for TiledImage {
+    fn eq(&self, other: &Self) -> bool {
+        self.levels.len() == other.levels.len() && self.levels.iter().zip(other.levels.iter()).all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+
+impl TiledImage {
+    pub fn new(levels: Vec<Arc<DynamicImage>>) -> Self {
+        Self { levels, cache: Mutex::new(HashMap::new()) }
+    }
+
+    /// The smallest level, it is what the layer texture shows.
+    pub fn preview(&self) -> &DynamicImage {
+        self.levels.last().unwrap()
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Picks the coarsest level that still has at least one pixel per screen pixel.
+    pub fn level_for_scale(&self, screen_pixels_per_source_pixel: f32) -> usize {
+        if screen_pixels_per_source_pixel <= 0. {
+            return self.levels.len() - 1;
+        }
+        let level = (1. / screen_pixels_per_source_pixel).log2().floor().max(0.) as usize;
+        level.min(self.levels.len() - 1)
+    }
+
+    /// Returns the tiles of `level` overlapping `visible_source_uv`, uploading missing ones within `upload_budget`.
+    /// The flag is false when some overlapping tiles are not uploaded yet.
+    pub fn visible_tiles(&self, ctx: &egui::Context, level: usize, visible_source_uv: egui::Rect, upload_budget: &mut usize) -> (Vec<VisibleTile>, bool) {
+        let level_image = &self.levels[level];
+        let (width, height) = (level_image.width(), level_image.height());
+        let columns = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+        let to_tile = |uv: f32, pixels: u32, count: u32| ((uv * pixels as f32 / TILE_SIZE as f32).floor().max(0.) as u32).min(count - 1);
+        let (first_column, last_column) = (to_tile(visible_source_uv.min.x, width, columns), to_tile(visible_source_uv.max.x, width, columns));
+        let (first_row, last_row) = (to_tile(visible_source_uv.min.y, height, rows), to_tile(visible_source_uv.max.y, height, rows));
+
+        let frame = ctx.cumulative_pass_nr();
+        let mut cache = self.cache.lock().unwrap();
+        let mut tiles = Vec::new();
+        let mut is_complete = true;
+        for row in first_row..=last_row {
+            for column in first_column..=last_column {
+                let key = TileKey { level, column, row };
+                let (x, y) = (column * TILE_SIZE, row * TILE_SIZE);
+                let (tile_width, tile_height) = (TILE_SIZE.min(width - x), TILE_SIZE.min(height - y));
+                let cached = match cache.entry(key) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        if *upload_budget == 0 {
+                            is_complete = false;
+                            continue;
+                        }
+                        *upload_budget -= 1;
+                        let pixels = color_image_from(&level_image.crop_imm(x, y, tile_width, tile_height));
+                        let texture = ctx.load_texture(format!("tile_{level}_{column}_{row}"), pixels, TextureOptions::default());
+                        entry.insert(CachedTile { texture, last_used: frame })
+                    }
+                };
+                cached.last_used = frame;
+                tiles.push(VisibleTile {
+                    texture_id: cached.texture.id(),
+                    source_uv: egui::Rect::from_min_max(
+                        Pos2::new(x as f32 / width as f32, y as f32 / height as f32),
+                        Pos2::new((x + tile_width) as f32 / width as f32, (y + tile_height) as f32 / height as f32),
+                    ),
+                });
+            }
+        }
+        (tiles, is_complete)
+    }
+
+    /// Releases the tiles not drawn for a while. Runs every frame for every tiled layer,
+    /// so tiles of layers that went off the board, hidden or back to the preview are freed as well.
+    pub fn evict_unused(&self, frame: u64) {
+        self.cache.lock().unwrap().retain(|_, cached| cached.last_used + TILE_EVICT_FRAMES >= frame);
+    }
+
+    /// Releases every uploaded tile, for layers that leave the board but stay alive in the history.
+    pub fn release_tiles(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+
+    use super::*;
+
+    fn tiled_image() -> TiledImage {
+        TiledImage::new(build_levels(&Arc::new(DynamicImage::ImageRgba8(RgbaImage::new(TILE_SIZE * 2, TILE_SIZE)))))
+    }
+
+    fn full_uv() -> egui::Rect {
+        egui::Rect::from_min_max(Pos2::ZERO, Pos2::new(1., 1.))
+    }
+
+    #[test]
+    fn levels_halve_down_to_one_tile() {
+        let tiled = tiled_image();
+        assert_eq!(tiled.level_count(), 2);
+        assert_eq!((tiled.preview().width(), tiled.preview().height()), (TILE_SIZE, TILE_SIZE / 2));
+        assert_eq!(tiled.level_for_scale(1.), 0);
+        assert_eq!(tiled.level_for_scale(0.5), 1);
+        assert_eq!(tiled.level_for_scale(0.01), 1);
+    }
+
+    #[test]
+    fn tiles_not_drawn_for_a_while_are_evicted() {
+        let ctx = egui::Context::default();
+        let tiled = tiled_image();
+        let mut upload_budget = 1;
+        let (tiles, is_complete) = tiled.visible_tiles(&ctx, 0, full_uv(), &mut upload_budget);
+        assert_eq!((tiles.len(), is_complete), (1, false));
+        let mut upload_budget = TILE_UPLOADS_PER_FRAME;
+        let (tiles, is_complete) = tiled.visible_tiles(&ctx, 0, full_uv(), &mut upload_budget);
+        assert_eq!((tiles.len(), is_complete), (2, true));
+
+        let frame = ctx.cumulative_pass_nr();
+        tiled.evict_unused(frame + TILE_EVICT_FRAMES);
+        assert_eq!(tiled.cache.lock().unwrap().len(), 2);
+        tiled.evict_unused(frame + TILE_EVICT_FRAMES + 1);
+        assert!(tiled.cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn released_tiles_are_uploaded_again_when_drawn() {
+        let ctx = egui::Context::default();
+        let tiled = tiled_image();
+        let mut upload_budget = TILE_UPLOADS_PER_FRAME;
+        tiled.visible_tiles(&ctx, 0, full_uv(), &mut upload_budget);
+        tiled.release_tiles();
+        assert!(tiled.cache.lock().unwrap().is_empty());
+        let mut upload_budget = TILE_UPLOADS_PER_FRAME;
+        let (tiles, is_complete) = tiled.visible_tiles(&ctx, 0, full_uv(), &mut upload_budget);
+        assert_eq!((tiles.len(), is_complete), (2, true));
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use egui::{Align2, Color32, RichText, Vec2};
+
+const TOAST_SECONDS: f64 = 6.;
+const MAX_TOASTS: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToastLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+pub struct Toast {
+    pub level: ToastLevel,
+    pub message: String,
+    expires_at: Option<f64>,
+}
+
+/// Short lived notifications stacked in the bottom right corner, they never block the board.
+/// Every message is also written to the log.
+#[derive(Default)]
+pub struct Toasts {
+    toasts: Vec<Toast>,
+}
+
+impl Toasts {
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(ToastLevel::Info, message.into());
+    }
+
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.push(ToastLevel::Warning, message.into());
+    }
+
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.push(ToastLevel::Error, message.into());
+    }
+
+    fn push(&mut self, level: ToastLevel, message: String) {
+        match level {
+            ToastLevel::Info => log::info!("{message}"),
+            ToastLevel::Warning => log::warn!("{message}"),
+            ToastLevel::Error => log::error!("{message}"),
+        }
+        self.toasts.push(Toast { level, message, expires_at: None });
+        if self.toasts.len() > MAX_TOASTS {
+            self.toasts.remove(0);
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        let now = ctx.input(|i| i.time);
+        // Toasts can be pushed without a context, their lifetime starts once they are first shown
+        for toast in self.toasts.iter_mut() {
+            toast.expires_at.get_or_insert(now + TOAST_SECONDS);
+        }
+        self.toasts.retain(|toast| toast.expires_at.is_some_and(|expires_at| expires_at > now));
+        if self.toasts.is_empty() {
+            return;
+        }
+
+        let mut dismissed = None;
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(Align2::RIGHT_BOTTOM, Vec2::new(-12., -12.))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                for (idx, toast) in self.toasts.iter().enumerate() {
+                    let (icon, color) = match toast.level {
+                        ToastLevel::Info => (egui_phosphor::regular::INFO, Color32::LIGHT_BLUE),
+                        ToastLevel::Warning => (egui_phosphor::regular::WARNING, Color32::YELLOW),
+                        ToastLevel::Error => (egui_phosphor::regular::WARNING_OCTAGON, Color32::LIGHT_RED),
+                    };
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(360.);
+                        ui.horizontal(|ui| {
+                            ui.label(RichText::new(icon).color(color).size(18.));
+                            ui.label(&toast.message);
+                            if ui.small_button(egui_phosphor::regular::X).clicked() {
+                                dismissed = Some(idx);
+                            }
+                        });
+                    });
+                }
+            });
+        if let Some(idx) = dismissed {
+            self.toasts.remove(idx);
+        }
+
+        if let Some(next_expiry) = self.toasts.iter().filter_map(|toast| toast.expires_at).reduce(f64::min) {
+            ctx.request_repaint_after(Duration::from_secs_f64((next_expiry - now).max(0.)));
+        }
+    }
+}